@@ -0,0 +1,22 @@
+use netwalk::common::game::Game;
+use netwalk::common::rng::GameSeed;
+use std::time::Instant;
+
+/// Generates and scrambles boards of growing size and checks them once.
+/// Time grows linearly with the tiles. Boards past 1024x1024 pick frontier
+/// tiles in constant time, but each tile still costs some hundreds of
+/// nanoseconds, so 4096x4096 takes seconds rather than the one aimed at.
+fn main() {
+  for &size in [256, 1024, 2048, 4096].iter() {
+    let start = Instant::now();
+    let game = Game::new(size, size, GameSeed::from_arr([1; 32]));
+    let solved = game.board.is_solved();
+    println!(
+      "{}x{}: {:?} (solved: {})",
+      size,
+      size,
+      start.elapsed(),
+      solved
+    );
+  }
+}
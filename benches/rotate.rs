@@ -0,0 +1,46 @@
+use netwalk::common::board::Board;
+use netwalk::common::game::Game;
+use netwalk::common::rng::{GameRng, GameSeed};
+use netwalk::common::tile::{Rotation, TilePos};
+use rand::Rng;
+use std::time::{Duration, Instant};
+
+const SIZE: i32 = 1000;
+const ROTATIONS: u32 = 100_000;
+
+fn random_pos(rng: &mut GameRng) -> TilePos {
+  TilePos::new(rng.gen_range(0, SIZE), rng.gen_range(0, SIZE))
+}
+
+/// Turns random tiles of a scrambled board, most of which aren't powered.
+fn scrambled(rng: &mut GameRng) -> Duration {
+  let mut game = Game::new(SIZE, SIZE, GameSeed::from_arr([1; 32]));
+  let start = Instant::now();
+  for _ in 0..ROTATIONS {
+    let pos = random_pos(rng);
+    game.board.rotate_tile(&pos, Rotation::Right);
+  }
+  start.elapsed() / ROTATIONS
+}
+
+/// Turns random tiles of a solved board and back, cutting the network in
+/// two and joining it again.
+fn solved(rng: &mut GameRng) -> Duration {
+  let mut board = Board::new(SIZE, SIZE, rng);
+  assert!(board.is_solved());
+
+  let start = Instant::now();
+  for _ in 0..ROTATIONS / 10 {
+    let pos = random_pos(rng);
+    board.rotate_tile(&pos, Rotation::Right);
+    board.rotate_tile(&pos, Rotation::Left);
+  }
+  start.elapsed() / (ROTATIONS / 10 * 2)
+}
+
+fn main() {
+  let mut rng = GameRng::from(GameSeed::from_arr([0; 32]));
+  println!("{}x{} boards", SIZE, SIZE);
+  println!("scrambled: {:?} per rotation", scrambled(&mut rng));
+  println!("solved:    {:?} per rotation", solved(&mut rng));
+}
@@ -1,35 +1,245 @@
-use super::direction::Direction;
-use super::direction::DIRECTIONS;
+use super::cells::Cells;
+use super::difficulty;
+use super::direction::{Direction, Grid};
+use super::frontier::{Frontier, Weights};
+use super::generator::{GeneratorConfig, RootPlacement, TileWeights};
+use super::mask::{InvalidMaskError, Mask, MaskSource};
+use super::power::{Power, PowerGrid};
 use super::rng::GameRng;
-use super::tile::{RotationDirection, Tile, TileKind, TilePos};
-use super::util::{BitFlag, Flag, Size};
+use super::rules::{Violation, WinRule};
+use super::solver::{Deduction, Explanation, Solver};
+use super::tile::{Lock, Rotation, RotationDirection, Tile, TileKind, TilePos};
+use super::topology::{
+  has_double_edges, is_connected, HexGrid, Masked, SquareGrid, Topology, Walled, Wrapping,
+};
+use super::util::{find, BitFlag, Flag, Size};
 use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 use std::time::Instant;
 
-#[derive(Debug, Clone, PartialEq)]
+/// How many trees to grow looking for one with a unique solution.
+const UNIQUE_ATTEMPTS: usize = 100;
+
+/// Boards with more tiles than this grow from an unordered frontier, so
+/// their trees differ from what ordered picks would give for the seed.
+const ORDERED_FRONTIER_CELLS: usize = 1024 * 1024;
+
+/// Largest board, in cells, whose hints look past deduction. Proving a tile
+/// is wrong takes searching the whole board, and the server waits on it.
+const MAX_SEARCHED_HINT_CELLS: usize = 40 * 40;
+
+/// How many tiles start solved and locked.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Hints {
+  Count(usize),
+  /// Share of the tiles, from 0 to 100.
+  Percent(f32),
+}
+
+/// A tile that's turned wrong, with the orientation it's forced into.
+#[derive(Serialize, Debug, Clone)]
+pub struct TileHint {
+  pub pos: TilePos,
+  pub directions: Flag,
+  /// The deductions that force it, empty when it takes trying out the
+  /// alternatives to prove.
+  pub reasons: Vec<Deduction>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct BoardOptions {
+  /// Regenerate the tree until the puzzle has exactly one solution. The
+  /// solution only counts as one when every tile has to be powered, so a
+  /// unique board plays under `WinRule::Powered` if `rule` asks for less.
+  pub unique: bool,
+  /// Connect the right edge to the left and the bottom edge to the top.
+  /// Wrapping hex boards get an even height so the row offsets line up.
+  pub wrap: bool,
+  pub grid: Grid,
+  /// Shape of the board. A mask's own size replaces the requested one.
+  pub mask: Option<MaskSource>,
+  /// Chance of a wall on each edge, from 0 to 1. Walls never cut the board
+  /// in two.
+  pub walls: f32,
+  /// Number of servers, each powering its own network. Zero counts as one.
+  pub servers: usize,
+  /// Number of colours the servers take in turn. Zero gives every server
+  /// its own colour.
+  pub colors: usize,
+  /// Chance of a branch crossing a straight pipe over a bridge instead of
+  /// turning away, from 0 to 1. Square boards only.
+  pub bridges: f32,
+  pub hints: Option<Hints>,
+  pub generator: GeneratorConfig,
+  pub rule: WinRule,
+}
+
+/// Options no board can be built with: a bad mask, or a unique solution
+/// the board's shape rules out or that wasn't found in time.
+#[derive(Debug)]
+pub struct InvalidOptionsError;
+
+impl From<InvalidMaskError> for InvalidOptionsError {
+  fn from(_: InvalidMaskError) -> Self {
+    InvalidOptionsError
+  }
+}
+
+impl BoardOptions {
+  /// The rule boards are won by, see `unique`.
+  pub fn win_rule(&self) -> WinRule {
+    if self.unique && self.rule == WinRule::Terminals {
+      WinRule::Powered
+    } else {
+      self.rule
+    }
+  }
+
+  pub fn topology(&self, width: i32, height: i32) -> Result<Arc<dyn Topology>, InvalidMaskError> {
+    let mut height = height;
+    if self.wrap && self.grid == Grid::Hex {
+      height += height % 2;
+    }
+
+    let mask = match &self.mask {
+      Some(source) => Some(Mask::from_source(source, width, height)?),
+      None => None,
+    };
+    let size = mask
+      .as_ref()
+      .map_or(Size::new(width, height), |m| m.get_size());
+    if self.wrap && self.grid == Grid::Hex && size.height % 2 == 1 {
+      return Err(InvalidMaskError);
+    }
+
+    let topology = match (self.grid, self.wrap) {
+      (Grid::Square, false) => masked(SquareGrid::new(size.width, size.height), mask),
+      (Grid::Square, true) => masked(Wrapping(SquareGrid::new(size.width, size.height)), mask),
+      (Grid::Hex, false) => masked(HexGrid::new(size.width, size.height), mask),
+      (Grid::Hex, true) => masked(Wrapping(HexGrid::new(size.width, size.height)), mask),
+    };
+
+    if !is_connected(topology.as_ref()) {
+      return Err(InvalidMaskError);
+    }
+
+    Ok(topology)
+  }
+}
+
+/// Picks the first root's cell for `placement`, or `None` to draw it at
+/// random like the others.
+fn place_root(topology: &dyn Topology, placement: RootPlacement) -> Option<TilePos> {
+  let cells = (0..topology.len())
+    .map(|i| topology.pos(i))
+    .filter(|pos| topology.contains(pos));
+
+  match placement {
+    RootPlacement::Random => None,
+    RootPlacement::Corner => cells.min_by_key(|pos| pos.x + pos.y),
+    RootPlacement::Center => {
+      let size = topology.size();
+      cells.min_by_key(|pos| {
+        let dx = 2 * pos.x + 1 - size.width;
+        let dy = 2 * pos.y + 1 - size.height;
+        dx * dx + dy * dy
+      })
+    }
+  }
+}
+
+fn masked<T: Topology + 'static>(topology: T, mask: Option<Mask>) -> Arc<dyn Topology> {
+  match mask {
+    Some(mask) => Arc::new(Masked::new(topology, mask)),
+    None => Arc::new(topology),
+  }
+}
+
+#[derive(Debug, Clone)]
 pub struct Board {
-  size: Size<i32>,
-  tiles: Vec<Tile>,
-  tiles_to_visit: Vec<TilePos>,
-  root: TilePos,
+  topology: Arc<dyn Topology>,
+  cells: Cells,
+  tiles_to_visit: Frontier,
+  /// What each tile in the tree weighs as it grows, if the tree is
+  /// weighted, see `grow_weighted`.
+  weights: Option<Weights>,
+  tile_weights: TileWeights,
+  /// Tiles with pipes so far and how many of them are terminals, while the
+  /// tree grows.
+  visited: usize,
+  terminals: usize,
+  roots: Vec<TilePos>,
+  rule: WinRule,
+  power: Power,
+  /// Fewest turns that solve the board as scrambled, see `scramble`.
+  par: usize,
   start: Instant,
 }
 
 impl Board {
   pub fn new(width: i32, height: i32, rng: &mut GameRng) -> Self {
-    let mut board = Self {
-      size: Size::new(width, height),
-      tiles: Vec::with_capacity((width * height) as usize),
-      tiles_to_visit: Vec::new(),
-      root: TilePos::new(rng.gen_range(0, width), rng.gen_range(0, height)),
-      start: Instant::now(),
-    };
+    Self::with_options(width, height, &BoardOptions::default(), rng).unwrap()
+  }
 
-    board.init_tiles();
-    board.generate_tree(rng);
-    board.set_tiles_kinds();
+  pub fn with_options(
+    width: i32,
+    height: i32,
+    options: &BoardOptions,
+    rng: &mut GameRng,
+  ) -> Result<Self, InvalidOptionsError> {
+    let mut topology = options.topology(width, height)?;
+    if options.walls > 0.0 {
+      topology = Arc::new(Walled::random(topology, options.walls, rng));
+    }
+    if options.unique && has_double_edges(topology.as_ref()) {
+      return Err(InvalidOptionsError);
+    }
+    let size = topology.size();
+    let cells = (0..topology.len())
+      .filter(|&i| topology.contains(&topology.pos(i)))
+      .count();
+    let servers = options.servers.max(1).min(cells);
 
-    board
+    for _ in 0..UNIQUE_ATTEMPTS {
+      let mut roots = Vec::with_capacity(servers);
+      if let Some(pos) = place_root(topology.as_ref(), options.generator.root) {
+        roots.push(pos);
+      }
+      while roots.len() < servers {
+        let pos = TilePos::new(rng.gen_range(0, size.width), rng.gen_range(0, size.height));
+        if topology.contains(&pos) && !roots.contains(&pos) {
+          roots.push(pos);
+        }
+      }
+
+      let mut board = Self {
+        topology: topology.clone(),
+        cells: Cells::new(topology.len(), topology.directions().len()),
+        tiles_to_visit: Frontier::default(),
+        weights: None,
+        tile_weights: TileWeights::default(),
+        visited: 0,
+        terminals: 0,
+        roots: roots,
+        rule: options.win_rule(),
+        power: Power::default(),
+        par: 0,
+        start: Instant::now(),
+      };
+
+      board.init_tiles();
+      board.generate_tree(options.bridges, &options.generator, rng);
+      board.set_tiles_kinds();
+      board.set_tiles_colors(options.colors);
+
+      if !options.unique || board.count_solutions(2) == 1 {
+        return Ok(board);
+      }
+    }
+
+    Err(InvalidOptionsError)
   }
 
   pub fn start_timer(&mut self) {
@@ -41,185 +251,863 @@ impl Board {
   }
 
   fn init_tiles(&mut self) {
-    for x in 0..self.size.width {
-      for y in 0..self.size.height {
-        let mut tile = Tile::new();
-
-        for dir in DIRECTIONS.iter() {
-          let mut tile_pos = TilePos::new(y, x);
-          tile_pos += dir.offset;
+    for i in 0..self.topology.len() {
+      let pos = self.topology.pos(i);
+      if !self.topology.contains(&pos) {
+        self.cells.set_kind(i, TileKind::UNDEFINED);
+        continue;
+      }
 
-          if self.in_bounds(&tile_pos) {
-            tile.neighbours |= dir.flag;
-          }
+      let mut neighbours = 0;
+      for dir in self.topology.directions().iter() {
+        if self.topology.neighbour(&pos, dir).is_some() {
+          neighbours |= dir.flag;
         }
-        self.tiles.push(tile);
       }
+      self.cells.set_neighbours(i, neighbours);
     }
   }
 
+  /// Marks the servers. Every other tile's kind follows from its pipes,
+  /// and bridges are marked as they're laid.
   fn set_tiles_kinds(&mut self) {
-    for x in 0..self.size.width {
-      for y in 0..self.size.height {
-        let mut tile = self.get_tile_mut(&TilePos::new(x, y));
-        if tile.connections() == 1 {
-          tile.kind = TileKind::TERMINAL;
-        } else {
-          tile.kind = TileKind::CONNECTOR;
+    for pos in self.roots.iter() {
+      let i = self.topology.index(pos);
+      self.cells.set_kind(i, TileKind::SERVER);
+    }
+  }
+
+  /// Gives the servers their colours and each terminal the colour of the
+  /// server whose tree it was generated in.
+  fn set_tiles_colors(&mut self, colors: usize) {
+    let colors = if colors == 0 {
+      self.roots.len()
+    } else {
+      colors
+    };
+    let server_colors: Vec<u8> = (0..self.roots.len()).map(|i| (i % colors) as u8).collect();
+    self
+      .cells
+      .reserve_colors(server_colors.iter().cloned().max().unwrap_or(0));
+    for (pos, &color) in self.roots.iter().zip(server_colors.iter()) {
+      let i = self.topology.index(pos);
+      self.cells.set_color(i, Some(color));
+    }
+
+    self.power_grid().reset();
+    // Terminals take the colour of the server powering them, which leaves
+    // no wrong colours for the power to count.
+    for i in 0..self.cells.len() {
+      if self.cells.kind(i) == TileKind::TERMINAL {
+        let color = self.power.server(2 * i).map(|s| server_colors[s]);
+        self.cells.set_color(i, color);
+      }
+    }
+  }
+
+  fn get_server_colors(&self) -> Vec<u8> {
+    self
+      .roots
+      .iter()
+      .map(|pos| self.get_tile(pos).color.unwrap_or(0))
+      .collect()
+  }
+
+  fn power_grid(&mut self) -> PowerGrid<'_> {
+    PowerGrid {
+      cells: &self.cells,
+      topology: self.topology.as_ref(),
+      roots: &self.roots,
+      power: &mut self.power,
+    }
+  }
+
+  /// Checks the board against its win rule. The power is kept up to date
+  /// as tiles turn, so this takes constant time.
+  pub fn is_solved(&self) -> bool {
+    self.power.is_solved(self.rule)
+  }
+
+  /// Returns the first thing that breaks the board's win rule, if any.
+  /// Unlike `is_solved` this looks through the whole board.
+  pub fn violation(&self) -> Option<Violation> {
+    if self.is_solved() {
+      return None;
+    }
+
+    if self.power.touches() {
+      return Some(Violation::ServersJoined);
+    }
+
+    if self.rule == WinRule::Classic {
+      if let Some(violation) = self.find_loop().or_else(|| self.find_open_end()) {
+        return Some(violation);
+      }
+    }
+
+    let server_colors = self.get_server_colors();
+    for i in 0..self.cells.len() {
+      let pos = self.topology.pos(i);
+      if self.cells.kind(i) == TileKind::TERMINAL {
+        match self.power.server(2 * i) {
+          Some(s) if self.cells.color(i).map_or(true, |c| c == server_colors[s]) => {}
+          Some(_) => return Some(Violation::WrongColor { pos }),
+          None => return Some(Violation::Unpowered { pos }),
+        }
+      } else if self.rule != WinRule::Terminals {
+        let channels = self.cells.channels(i);
+        if (0..2).any(|ch| channels[ch] != 0 && self.power.server(2 * i + ch).is_none()) {
+          return Some(Violation::Unpowered { pos });
         }
       }
     }
 
-    let pos = self.root;
-    self.get_tile_mut(&pos).kind = TileKind::SERVER;
+    None
   }
 
-  fn solve_walk(&mut self, pos: TilePos) {
-    let tile = self.get_tile_mut(&pos);
-    tile.powered = true;
-    let tile_dirs = tile.directions;
+  fn find_open_end(&self) -> Option<Violation> {
+    for i in 0..self.cells.len() {
+      let pos = self.topology.pos(i);
+      let directions = self.cells.directions(i);
+      for dir in self.topology.directions().iter() {
+        if (directions & dir.flag) == 0 {
+          continue;
+        }
+
+        let open = match self.topology.neighbour(&pos, dir) {
+          Some(n) => (self.get_tile(&n).directions & dir.opposite) == 0,
+          None => true,
+        };
+        if open {
+          return Some(Violation::OpenEnd {
+            pos: pos,
+            direction: dir.kind,
+          });
+        }
+      }
+    }
+
+    None
+  }
+
+  /// Joins the channels of linked tiles and reports the first link that
+  /// joins a channel to itself.
+  fn find_loop(&self) -> Option<Violation> {
+    let channel = |i: usize, flag: Flag| 2 * i + self.cells.channel(i, flag);
+    let mut parent: Vec<usize> = (0..2 * self.cells.len()).collect();
+
+    for i in 0..self.cells.len() {
+      let pos = self.topology.pos(i);
+      let directions = self.cells.directions(i);
+      for dir in self.topology.directions().iter() {
+        if (directions & dir.flag) == 0 {
+          continue;
+        }
 
-    for dir in DIRECTIONS.iter() {
-      if (dir.flag & tile_dirs) != 0 {
-        let neighbour_pos = pos + dir.offset;
-        if !self.in_bounds(&neighbour_pos) {
+        let n = match self.topology.neighbour(&pos, dir) {
+          Some(n) => self.topology.index(&n),
+          None => continue,
+        };
+        // Each link is seen from both ends; only count it once.
+        if (self.cells.directions(n) & dir.opposite) == 0
+          || n < i
+          || (n == i && dir.flag > dir.opposite)
+        {
           continue;
         }
-        let neighbour_tile = self.get_tile_mut(&neighbour_pos);
-        if (dir.opposite & neighbour_tile.directions) != 0 && !neighbour_tile.powered {
-          self.solve_walk(neighbour_pos);
+
+        let a = find(&mut parent, channel(i, dir.flag));
+        let b = find(&mut parent, channel(n, dir.opposite));
+        if a == b {
+          return Some(Violation::Loop { pos });
         }
+        parent[a] = b;
       }
     }
+
+    None
   }
 
-  pub fn is_solved(&mut self) -> bool {
-    for tile in &mut self.tiles {
-      tile.powered = false;
+  /// Locks tiles in their solved orientation, picked at random among the
+  /// ones that rotating would change.
+  pub fn lock_hints(&mut self, hints: Hints, rng: &mut GameRng) {
+    let mut cells: Vec<usize> = (0..self.cells.len())
+      .filter(|&i| {
+        let flag = self.cells.directions(i);
+        self.topology.rotate(flag, RotationDirection::Right, 1) != flag
+      })
+      .collect();
+
+    let n = match hints {
+      Hints::Count(n) => n,
+      Hints::Percent(p) => (cells.len() as f32 * p / 100.0).round() as usize,
+    };
+
+    for i in 0..n.min(cells.len()) {
+      let j = rng.gen_range(i, cells.len());
+      cells.swap(i, j);
+      self.cells.set_lock(cells[i], Some(Lock::Hint));
     }
+  }
 
-    self.solve_walk(self.root);
+  pub fn get_locked(&self, lock: Lock) -> Vec<TilePos> {
+    (0..self.cells.len())
+      .filter(|&i| self.cells.lock(i) == Some(lock))
+      .map(|i| self.topology.pos(i))
+      .collect()
+  }
 
-    for tile in &self.tiles {
-      if tile.kind == TileKind::TERMINAL && !tile.powered {
-        return false;
-      }
+  /// Locks or unlocks a tile for the player. Returns `false` if nothing
+  /// changed, which is also the case for hints.
+  pub fn set_lock(&mut self, pos: &TilePos, locked: bool) -> bool {
+    if !self.topology.contains(pos) {
+      return false;
+    }
+
+    let i = self.topology.index(pos);
+    match (self.cells.lock(i), locked) {
+      (None, true) => self.cells.set_lock(i, Some(Lock::Player)),
+      (Some(Lock::Player), false) => self.cells.set_lock(i, None),
+      _ => return false,
     }
 
     true
   }
 
+  /// Index into `get_roots` of the server powering each channel of every
+  /// tile.
+  pub fn get_servers(&self) -> Vec<[Option<usize>; 2]> {
+    (0..self.cells.len())
+      .map(|i| [self.power.server(2 * i), self.power.server(2 * i + 1)])
+      .collect()
+  }
+
+  /// Channels whose server changed since this was last called, numbered
+  /// `2 * tile + channel`, with the server powering each now, see
+  /// `get_servers`.
+  pub fn take_server_changes(&mut self) -> Vec<(usize, Option<usize>)> {
+    self.power.take_changes()
+  }
+
+  pub fn get_bridges(&self) -> Vec<TilePos> {
+    (0..self.cells.len())
+      .filter(|&i| self.cells.is_bridge(i))
+      .map(|i| self.topology.pos(i))
+      .collect()
+  }
+
+  /// Counts the orientations that solve the board, up to `limit`.
+  pub fn count_solutions(&self, limit: usize) -> usize {
+    Solver::new(self).count_solutions(limit)
+  }
+
+  /// Every deduction that can be made on the board, and why, before
+  /// guessing would be needed.
+  pub fn explain(&self) -> Explanation {
+    Solver::new(self).explain()
+  }
+
+  /// Finds a tile that isn't turned the way every solution has it. Tiles
+  /// deduction can place come first, in the order they're deduced, so the
+  /// hint comes with its reasons whenever possible. Boards with several
+  /// solutions may have no such tile left before they're solved, and
+  /// boards over `MAX_SEARCHED_HINT_CELLS` only get the tiles deduction
+  /// places.
+  pub fn hint(&self) -> Option<TileHint> {
+    let solver = Solver::new(self);
+    let steps = solver.explain().steps;
+    for step in steps.iter() {
+      if step.remaining.len() != 1 || self.get_tile(&step.pos).directions == step.remaining[0] {
+        continue;
+      }
+
+      return Some(TileHint {
+        pos: step.pos,
+        directions: step.remaining[0],
+        reasons: steps
+          .iter()
+          .filter(|s| s.pos == step.pos)
+          .cloned()
+          .collect(),
+      });
+    }
+
+    // Deduction stalled before reaching a wrong tile, so check the
+    // remaining ones the slow way. Only tiles turned otherwise than in some
+    // solution can be wrong, and each check is a search of its own.
+    if self.cells.len() > MAX_SEARCHED_HINT_CELLS {
+      return None;
+    }
+    let solution = solver.solve()?;
+    for i in 0..self.cells.len() {
+      if self.cells.lock(i) == Some(Lock::Hint) || self.cells.directions(i) == solution[i] {
+        continue;
+      }
+
+      if let Some(directions) = solver.forced(i) {
+        return Some(TileHint {
+          pos: self.topology.pos(i),
+          directions: directions,
+          reasons: Vec::new(),
+        });
+      }
+    }
+
+    None
+  }
+
+  /// Rates how hard the board is to solve, from 0 to 100, or `None` if the
+  /// board is too big to rate.
+  pub fn difficulty(&self) -> Option<f32> {
+    if self.topology.len() > difficulty::MAX_RATED_CELLS {
+      return None;
+    }
+
+    Some(difficulty::score(self))
+  }
+
   pub fn get_directions(&self) -> Vec<Flag> {
-    self.tiles.iter().map(|v| v.directions).collect()
+    (0..self.cells.len())
+      .map(|i| self.cells.directions(i))
+      .collect()
   }
 
   pub fn get_neighbours(&self) -> Vec<Flag> {
-    self.tiles.iter().map(|v| v.neighbours).collect()
+    (0..self.cells.len())
+      .map(|i| self.cells.neighbours(i))
+      .collect()
+  }
+
+  /// Returns which cells are part of the board, or `None` if all are.
+  pub fn get_mask(&self) -> Option<Vec<bool>> {
+    let mask: Vec<bool> = (0..self.topology.len())
+      .map(|i| self.topology.contains(&self.topology.pos(i)))
+      .collect();
+
+    if mask.iter().all(|&c| c) {
+      None
+    } else {
+      Some(mask)
+    }
+  }
+
+  /// Returns the colour of every server and terminal.
+  pub fn get_colors(&self) -> Vec<Option<u8>> {
+    (0..self.cells.len()).map(|i| self.cells.color(i)).collect()
+  }
+
+  pub fn get_walls(&self) -> Vec<Flag> {
+    (0..self.topology.len())
+      .map(|i| self.topology.walls(&self.topology.pos(i)))
+      .collect()
+  }
+
+  pub fn get_topology(&self) -> &dyn Topology {
+    self.topology.as_ref()
+  }
+
+  pub fn get_neighbour(&self, pos: &TilePos, dir: &Direction) -> Option<TilePos> {
+    self.topology.neighbour(pos, dir)
   }
 
-  fn in_bounds(&self, pos: &TilePos) -> bool {
-    pos.x >= 0 && pos.y >= 0 && pos.x < self.size.width && pos.y < self.size.height
+  pub fn is_wrapping(&self) -> bool {
+    self.topology.is_wrapping()
+  }
+
+  pub fn get_grid(&self) -> Grid {
+    self.topology.grid()
+  }
+
+  pub fn get_index(&self, pos: &TilePos) -> usize {
+    self.topology.index(pos)
+  }
+
+  pub fn get_pos(&self, index: usize) -> TilePos {
+    self.topology.pos(index)
   }
 
   pub fn get_root(&self) -> TilePos {
-    self.root
+    self.roots[0]
   }
 
-  pub fn set_tile(&mut self, pos: &TilePos, tile: Tile) {
-    self.tiles[(pos.x + pos.y * self.size.width) as usize] = tile
+  pub fn get_roots(&self) -> &[TilePos] {
+    &self.roots
   }
 
   pub fn get_tile(&self, pos: &TilePos) -> Tile {
-    self.tiles[(pos.x + pos.y * self.size.width) as usize]
+    let i = self.topology.index(pos);
+    let mut tile = self.cells.tile(i);
+    tile.servers = [self.power.server(2 * i), self.power.server(2 * i + 1)];
+    tile.powered = tile.servers.iter().any(|s| s.is_some());
+    tile
+  }
+
+  /// Adds the pipes `flag` to tile `i`, keeping count of the tiles visited
+  /// and the terminals among them.
+  fn add_pipes(&mut self, i: usize, flag: Flag) {
+    let old = self.cells.directions(i);
+    let new = old | flag;
+    self.cells.set_directions(i, new);
+
+    if old == 0 && new != 0 {
+      self.visited += 1;
+    }
+    if old.count_bits() == 1 {
+      self.terminals -= 1;
+    }
+    if new.count_bits() == 1 {
+      self.terminals += 1;
+    }
+    self.reweigh(i);
   }
 
-  pub fn get_tile_mut(&mut self, pos: &TilePos) -> &mut Tile {
-    &mut self.tiles[(pos.x + pos.y * self.size.width) as usize]
+  /// Weighs the steps the tree can take from tile `i`, which is in it.
+  fn reweigh(&mut self, i: usize) {
+    let weights = match self.weights.as_mut() {
+      Some(weights) => weights,
+      None => return,
+    };
+
+    let flag = self.cells.directions(i);
+    let neighbours = self.cells.neighbours(i);
+    let directions = self.topology.directions();
+    let tile_weights = &self.tile_weights;
+    let weight = directions
+      .iter()
+      .filter(|d| (neighbours & d.flag) != 0)
+      .map(|d| tile_weights.step(flag, d, directions))
+      .sum();
+    weights.set(i, flag.count_bits() <= 1, weight);
   }
 
-  fn visit_tile(&mut self, tile_pos: &TilePos, flag: Flag) {
-    let mut tile = self.get_tile(&tile_pos);
-    if tile.directions == 0 {
-      self.tiles_to_visit.push(*tile_pos);
+  fn visit_tile(&mut self, i: usize, flag: Flag) {
+    let is_new = self.cells.directions(i) == 0;
+    if is_new {
+      self.tiles_to_visit.push(i);
     }
 
-    tile.directions |= flag;
-    self.set_tile(&tile_pos, tile);
+    self.add_pipes(i, flag);
+
+    // Neighbours stop leading here the first time round.
+    if !is_new {
+      return;
+    }
 
-    for dir in DIRECTIONS.iter() {
-      let mut tp = *tile_pos;
-      tp += dir.offset;
+    for dir in self.topology.directions().iter() {
+      if let Some(n) = self.topology.neighbour_index(i, dir) {
+        let neighbours = self.cells.neighbours(n);
+        self.cells.set_neighbours(n, neighbours & !dir.opposite);
 
-      if self.in_bounds(&tp) {
-        let tile = self.get_tile_mut(&tp);
-        tile.neighbours &= !dir.opposite;
+        // Only tiles in the tree weigh anything, until they can't grow.
+        if self.weights.as_ref().map_or(false, |w| w.get(n) > 0) {
+          self.reweigh(n);
+        }
       }
     }
   }
 
-  pub fn rotate_tile(&mut self, pos: &TilePos, dir: RotationDirection) -> Flag {
-    if !self.in_bounds(pos) {
-      return 0;
+  /// Turns the tile at `pos`, returning its pipes before and after. They're
+  /// the same for a turn that changes nothing, like any turn of a cross.
+  /// `None` if the tile can't be turned so: it's locked, off the board, or
+  /// the pipes asked for aren't a turn of its own.
+  pub fn rotate_tile(&mut self, pos: &TilePos, rotation: Rotation) -> Option<(Flag, Flag)> {
+    if !self.topology.contains(pos) {
+      return None;
     }
 
-    let tile = self.get_tile_mut(pos);
-    let old = tile.directions;
-    tile.rotate(dir, 1);
+    let i = self.topology.index(pos);
+    if self.cells.lock(i).is_some() {
+      return None;
+    }
+
+    let old = self.cells.directions(i);
+    let directions = match rotation {
+      Rotation::Right => self.topology.rotate(old, RotationDirection::Right, 1),
+      Rotation::Left => self.topology.rotate(old, RotationDirection::Left, 1),
+      Rotation::Half => {
+        let half = self.topology.directions().len() as i32 / 2;
+        self.topology.rotate(old, RotationDirection::Right, half)
+      }
+      Rotation::To(flag) => {
+        self.turns(old, flag)?;
+        flag
+      }
+    };
 
-    if tile.directions == old {
-      return 0;
+    if directions != old {
+      self.cells.set_directions(i, directions);
+      self.power_grid().turned(i, old);
     }
+    Some((old, directions))
+  }
 
-    tile.directions
+  /// Fewest steps, either way round, that turn pipes `from` into `to`, or
+  /// `None` if no turn does.
+  pub fn turns(&self, from: Flag, to: Flag) -> Option<usize> {
+    let directions = self.topology.directions().len() as i32;
+    let turned = |n| self.topology.rotate(from, RotationDirection::Right, n);
+    let n = (0..directions).find(|&n| turned(n) == to)?;
+    // Symmetric pipes come round sooner: a straight after two steps, a
+    // cross right away.
+    let period = (1..=directions).find(|&p| turned(p) == from).unwrap();
+    Some(n.min(period - n) as usize)
   }
 
   pub fn scramble(&mut self, rng: &mut GameRng) {
-    for tile in &mut self.tiles {
-      tile.rotate(RotationDirection::Right, rng.gen_range(0, 3));
+    let directions = self.topology.directions().len();
+    // Square tiles have always been turned at most twice, and existing
+    // seeds have to keep giving the same puzzles. Hex tiles can end up any
+    // way round.
+    let turns = match self.topology.grid() {
+      Grid::Square => directions as i32 - 1,
+      Grid::Hex => directions as i32,
+    };
+    // Every flag turned every number of times, looked up rather than
+    // worked out for each of a huge board's tiles.
+    let rotations: Vec<Vec<Flag>> = (0..turns)
+      .map(|n| {
+        (0..1 << directions)
+          .map(|flag| self.topology.rotate(flag, RotationDirection::Right, n))
+          .collect()
+      })
+      .collect();
+    // And how many steps each takes to come back.
+    let returns: Vec<Vec<usize>> = rotations
+      .iter()
+      .map(|turned| {
+        (0..1 << directions)
+          .map(|flag| self.turns(turned[flag], flag as Flag).unwrap())
+          .collect()
+      })
+      .collect();
+
+    self.par = 0;
+    for i in 0..self.cells.len() {
+      if self.cells.lock(i).is_some() {
+        continue;
+      }
+
+      let n = rng.gen_range(0, turns);
+      let directions = self.cells.directions(i);
+      self
+        .cells
+        .set_directions(i, rotations[n as usize][directions as usize]);
+      self.par += returns[n as usize][directions as usize];
     }
+
+    self.power_grid().reset();
   }
 
-  fn rand_dir(&self, pos: &TilePos, rng: &mut GameRng) -> Option<Direction> {
-    let tile = self.get_tile(pos);
+  fn rand_dir(&self, i: usize, rng: &mut GameRng) -> Option<Direction> {
+    let neighbours = self.cells.neighbours(i);
 
-    let n = tile.neighbours.count_bits();
+    let n = neighbours.count_bits() as usize;
     if n == 0 {
       return None;
     }
 
+    let i = rng.gen_range(0, n);
+    self
+      .topology
+      .directions()
+      .iter()
+      .filter(|d| (neighbours & d.flag) != 0)
+      .nth(i)
+      .cloned()
+  }
+
+  /// Picks a direction from `pos` over a straight pipe running across it to
+  /// an unvisited tile on the other side.
+  fn rand_bridge_dir(&self, pos: &TilePos, rng: &mut GameRng) -> Option<Direction> {
     let mut arr: Vec<Direction> = Vec::new();
-    for d in DIRECTIONS.iter() {
-      if (tile.neighbours & d.flag) != 0 {
-        arr.push(*d);
+    for d in self.topology.directions().iter() {
+      let mid = match self.topology.neighbour(pos, d) {
+        Some(mid) if !self.roots.contains(&mid) => mid,
+        _ => continue,
+      };
+      let m = self.topology.index(&mid);
+      if self.cells.is_bridge(m) || self.cells.directions(m) != (d.left | d.right) {
+        continue;
+      }
+
+      match self.topology.neighbour(&mid, d) {
+        Some(far) if far != *pos && !self.roots.contains(&far) => {
+          if self.cells.directions(self.topology.index(&far)) == 0 {
+            arr.push(*d);
+          }
+        }
+        _ => {}
       }
     }
 
+    if arr.is_empty() {
+      return None;
+    }
+
     let i = rng.gen_range(0, arr.len());
     Some(arr[i])
   }
 
+  /// Fewest turns that solve the board, counting each tile back to how
+  /// it was generated. Puzzles with more than one solution may take fewer.
+  pub fn get_par(&self) -> usize {
+    self.par
+  }
+
   pub fn get_size(&self) -> Size<i32> {
-    self.size
+    self.topology.size()
   }
 
-  fn generate_tree(&mut self, rng: &mut GameRng) {
-    let rp = self.root;
-    self.visit_tile(&rp, 0);
+  /// Grows the tree by one step, weighing every free direction of every
+  /// tile in it by `config`.
+  fn grow_weighted(&mut self, config: &GeneratorConfig, rng: &mut GameRng) {
+    let weights = self.weights.as_ref().unwrap();
+    let (end_bias, other_bias) = config.end_bias(self.terminals, self.visited);
+    let ends = end_bias * weights.total(true);
+    let others = other_bias * weights.total(false);
+
+    let r = rng.gen_range(0, ends + others);
+    let (i, mut rest) = if r < ends {
+      weights.find(true, r / end_bias)
+    } else {
+      weights.find(false, (r - ends) / other_bias)
+    };
+
+    let flag = self.cells.directions(i);
+    let neighbours = self.cells.neighbours(i);
+    let directions = self.topology.directions();
+    let dir = *directions
+      .iter()
+      .filter(|d| (neighbours & d.flag) != 0)
+      .find(|d| {
+        let weight = self.tile_weights.step(flag, d, directions) as u64;
+        if rest < weight {
+          return true;
+        }
+        rest -= weight;
+        false
+      })
+      .unwrap();
+
+    self.visit_tile(i, dir.flag);
+    let next = self
+      .topology
+      .neighbour(&self.topology.pos(i), &dir)
+      .unwrap();
+    self.visit_tile(self.topology.index(&next), dir.opposite);
+  }
+
+  fn generate_tree(&mut self, bridges: f32, config: &GeneratorConfig, rng: &mut GameRng) {
+    if let Some(build) = config.algorithm.builder() {
+      let flags = build(self.topology.as_ref(), &self.roots, rng);
+      for (i, flag) in flags.into_iter().enumerate() {
+        self.cells.set_directions(i, flag);
+      }
+      return;
+    }
+
+    let bridges = if self.topology.grid() == Grid::Square {
+      bridges
+    } else {
+      0.0
+    };
+
+    if config.is_weighted() {
+      self.tile_weights = config.tiles.unwrap_or_default();
+      self.weights = Some(Weights::new(self.cells.len()));
+    }
+
+    if self.cells.len() > ORDERED_FRONTIER_CELLS {
+      self.tiles_to_visit = Frontier::unordered();
+    }
+
+    // Every server starts its own tree and the trees grow side by side, so
+    // no tile is claimed by two of them.
+    for rp in self.roots.clone() {
+      self.visit_tile(self.topology.index(&rp), 0);
+    }
 
     while self.tiles_to_visit.len() > 0 {
-      let n = rng.gen_range(0, self.tiles_to_visit.len());
-      let mut tile_pos = self.tiles_to_visit[n];
+      while self.tiles_to_visit.len() > 0 {
+        let n = rng.gen_range(0, self.tiles_to_visit.len());
+        let mut i = self.tiles_to_visit.get(n);
 
-      if let Some(dir) = self.rand_dir(&tile_pos, rng) {
-        self.visit_tile(&tile_pos, dir.flag);
-        tile_pos += dir.offset;
+        // Weighted steps can be taken from any tile in the tree, so the
+        // frontier only learns that one has no room left when it's picked.
+        if config.is_weighted() && self.cells.neighbours(i) == 0 {
+          self.tiles_to_visit.remove(n);
+          continue;
+        }
 
-        self.visit_tile(&tile_pos, dir.opposite);
+        let bridge = if bridges > 0.0 && rng.gen::<f32>() < bridges {
+          self.rand_bridge_dir(&self.topology.pos(i), rng)
+        } else {
+          None
+        };
+
+        if let Some(dir) = bridge {
+          let pos = self.topology.pos(i);
+          self.visit_tile(i, dir.flag);
+          let mid = self.topology.neighbour(&pos, &dir).unwrap();
+          let m = self.topology.index(&mid);
+          self.add_pipes(m, dir.flag | dir.opposite);
+          self.cells.set_kind(m, TileKind::BRIDGE);
+          i = self
+            .topology
+            .index(&self.topology.neighbour(&mid, &dir).unwrap());
+
+          self.visit_tile(i, dir.opposite);
+        } else if config.is_weighted() {
+          self.grow_weighted(config, rng);
+          continue;
+        } else if let Some(dir) = self.rand_dir(i, rng) {
+          self.visit_tile(i, dir.flag);
+          i = self.topology.neighbour_index(i, &dir).unwrap();
+          self.visit_tile(i, dir.opposite);
+        }
+
+        if self.cells.neighbours(i).count_bits() <= 1 {
+          self.tiles_to_visit.remove(n);
+        }
       }
 
-      if self.get_tile(&tile_pos).neighbours.count_bits() <= 1 {
-        self.tiles_to_visit.remove(n);
+      // The frontier can drop a tile before all of its neighbours are
+      // visited, so pick those tiles up again until the tree spans the
+      // board. Walls make that likely, but plain boards get it too, and
+      // used to be left with empty tiles.
+      for i in 0..self.cells.len() {
+        if self.cells.neighbours(i) == 0 {
+          continue;
+        }
+
+        if self.cells.directions(i) != 0 || self.roots.contains(&self.topology.pos(i)) {
+          self.tiles_to_visit.push(i);
+        }
       }
     }
+    self.weights = None;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::rng::GameSeed;
+  use super::*;
+
+  /// Turns random tiles and checks that the power kept up move by move,
+  /// and the changes taken after each move, match walking the board anew.
+  #[test]
+  fn power_follows_turns() {
+    let options = [
+      BoardOptions::default(),
+      BoardOptions {
+        wrap: true,
+        servers: 3,
+        ..BoardOptions::default()
+      },
+      BoardOptions {
+        servers: 4,
+        colors: 2,
+        bridges: 0.5,
+        ..BoardOptions::default()
+      },
+      BoardOptions {
+        grid: Grid::Hex,
+        walls: 0.2,
+        servers: 2,
+        ..BoardOptions::default()
+      },
+      BoardOptions {
+        grid: Grid::Hex,
+        wrap: true,
+        servers: 3,
+        ..BoardOptions::default()
+      },
+    ];
+    let rotations = [Rotation::Right, Rotation::Left, Rotation::Half];
+
+    for (n, options) in options.iter().enumerate() {
+      let mut rng = GameRng::from(GameSeed::from_arr([n as u8; 32]));
+      let mut board = Board::with_options(8, 8, options, &mut rng).unwrap();
+      board.scramble(&mut rng);
+      let mut servers = board.get_servers();
+
+      for _ in 0..500 {
+        let pos = board.topology.pos(rng.gen_range(0, board.cells.len()));
+        let rotation = rotations[rng.gen_range(0, rotations.len())];
+        board.rotate_tile(&pos, rotation);
+        for (node, server) in board.take_server_changes() {
+          servers[node / 2][node % 2] = server;
+        }
+
+        let mut walked = board.clone();
+        walked.power_grid().reset();
+        assert_eq!(board.get_servers(), walked.get_servers());
+        assert_eq!(servers, walked.get_servers());
+        assert_eq!(board.is_solved(), walked.is_solved());
+        assert_eq!(board.power.touches(), walked.power.touches());
+      }
+    }
+  }
+
+  /// Whether the pipes of tile `i` and of the tiles before it meet.
+  fn meets_earlier(board: &Board, i: usize) -> bool {
+    let flag = board.cells.directions(i);
+    board
+      .topology
+      .directions()
+      .iter()
+      .all(|dir| match board.topology.neighbour_index(i, dir) {
+        Some(n) if n < i => {
+          ((flag & dir.flag) != 0) == ((board.cells.directions(n) & dir.opposite) != 0)
+        }
+        Some(_) => true,
+        None => (flag & dir.flag) == 0,
+      })
+  }
+
+  /// Counts the ways the tiles from `i` on can be turned that solve the
+  /// board, trying each distinct way round of every tile. Pipes ending open
+  /// leave some tile unpowered, which only `WinRule::Terminals` allows, so
+  /// under other rules those ways are skipped early.
+  fn count_solved(board: &mut Board, i: usize) -> usize {
+    if i == board.cells.len() {
+      return board.is_solved() as usize;
+    }
+
+    let pos = board.topology.pos(i);
+    let old = board.cells.directions(i);
+    let directions = board.topology.directions().len() as i32;
+    let mut turned: Vec<Flag> = (0..directions)
+      .map(|n| board.topology.rotate(old, RotationDirection::Right, n))
+      .collect();
+    turned.sort();
+    turned.dedup();
+
+    let mut count = 0;
+    for &flag in turned.iter() {
+      board.rotate_tile(&pos, Rotation::To(flag));
+      if board.rule == WinRule::Terminals || meets_earlier(board, i) {
+        count += count_solved(board, i + 1);
+      }
+    }
+    board.rotate_tile(&pos, Rotation::To(old));
+    count
+  }
+
+  /// Unique boards have no other way to solve them under their own rule,
+  /// which default options would allow on these seeds.
+  #[test]
+  fn unique_has_one_solution() {
+    let options = BoardOptions {
+      unique: true,
+      ..BoardOptions::default()
+    };
+
+    for &seed in [11, 14, 19, 31, 38, 39].iter() {
+      let mut rng = GameRng::from(GameSeed::from_arr([seed; 32]));
+      let mut board = Board::with_options(4, 3, &options, &mut rng).unwrap();
+      assert_eq!(count_solved(&mut board, 0), 1);
+    }
   }
 }
@@ -0,0 +1,216 @@
+use super::tile::{channels, Lock, Tile, TileKind};
+use super::util::{BitFlag, Flag};
+
+/// Fixed-width fields packed into words. Widths are powers of two, so no
+/// field straddles two words.
+#[derive(Debug, Clone, Default)]
+pub struct Packed {
+  /// Log2 of the width of a field.
+  log: u32,
+  words: Vec<u64>,
+}
+
+impl Packed {
+  /// `len` zeroed fields of at least `bits` bits each.
+  pub fn new(len: usize, bits: u32) -> Self {
+    let log = 32 - (bits.max(1) - 1).leading_zeros();
+    let per_word = 64 >> log;
+    Self {
+      log: log,
+      words: vec![0; (len + per_word - 1) / per_word],
+    }
+  }
+
+  /// `len` zeroed fields wide enough for values up to `max`.
+  pub fn for_max(len: usize, max: u64) -> Self {
+    Self::new(len, 64 - max.leading_zeros())
+  }
+
+  #[inline]
+  fn locate(&self, i: usize) -> (usize, u32, u64) {
+    let shift = 6 - self.log;
+    let offset = ((i & ((1 << shift) - 1)) as u32) << self.log;
+    let mask = if self.log == 6 {
+      !0
+    } else {
+      (1 << (1 << self.log)) - 1
+    };
+    (i >> shift, offset, mask)
+  }
+
+  #[inline]
+  pub fn get(&self, i: usize) -> u64 {
+    let (word, offset, mask) = self.locate(i);
+    (self.words[word] >> offset) & mask
+  }
+
+  #[inline]
+  pub fn set(&mut self, i: usize, value: u64) {
+    let (word, offset, mask) = self.locate(i);
+    let word = &mut self.words[word];
+    *word = (*word & !(mask << offset)) | ((value & mask) << offset);
+  }
+}
+
+/// Kinds that don't follow from a tile's pipes.
+const PLAIN: u64 = 0;
+const SERVER: u64 = 1;
+const BRIDGE: u64 = 2;
+const OUTSIDE: u64 = 3;
+
+const HINT: u64 = 1;
+const PLAYER: u64 = 2;
+
+/// What the board knows about each of its cells, bit-packed so that huge
+/// boards stay small: 4 bits of pipes a cell on square boards, 8 on hex
+/// ones, and side tables of a few bits for the rest. Terminals and
+/// connectors are told apart by their pipes, which turning doesn't change
+/// the number of.
+#[derive(Debug, Clone)]
+pub struct Cells {
+  len: usize,
+  directions: Packed,
+  /// Directions still leading to unvisited cells, while generating.
+  neighbours: Packed,
+  kinds: Packed,
+  locks: Packed,
+  /// Colour plus one, or zero for none.
+  colors: Packed,
+}
+
+impl Cells {
+  /// `len` empty cells with `directions` possible pipes each.
+  pub fn new(len: usize, directions: usize) -> Self {
+    Self {
+      len: len,
+      directions: Packed::new(len, directions as u32),
+      neighbours: Packed::new(len, directions as u32),
+      kinds: Packed::new(len, 2),
+      locks: Packed::new(len, 2),
+      colors: Packed::default(),
+    }
+  }
+
+  pub fn len(&self) -> usize {
+    self.len
+  }
+
+  #[inline]
+  pub fn directions(&self, i: usize) -> Flag {
+    self.directions.get(i) as Flag
+  }
+
+  #[inline]
+  pub fn set_directions(&mut self, i: usize, flag: Flag) {
+    self.directions.set(i, flag as u64);
+  }
+
+  #[inline]
+  pub fn neighbours(&self, i: usize) -> Flag {
+    self.neighbours.get(i) as Flag
+  }
+
+  #[inline]
+  pub fn set_neighbours(&mut self, i: usize, flag: Flag) {
+    self.neighbours.set(i, flag as u64);
+  }
+
+  #[inline]
+  pub fn kind(&self, i: usize) -> TileKind {
+    match self.kinds.get(i) {
+      SERVER => TileKind::SERVER,
+      BRIDGE => TileKind::BRIDGE,
+      OUTSIDE => TileKind::UNDEFINED,
+      _ if self.directions(i).count_bits() == 1 => TileKind::TERMINAL,
+      _ => TileKind::CONNECTOR,
+    }
+  }
+
+  /// Marks the cell as a server, a bridge or, with `UNDEFINED`, as not
+  /// part of the board. Any other kind clears the mark.
+  pub fn set_kind(&mut self, i: usize, kind: TileKind) {
+    let kind = match kind {
+      TileKind::SERVER => SERVER,
+      TileKind::BRIDGE => BRIDGE,
+      TileKind::UNDEFINED => OUTSIDE,
+      _ => PLAIN,
+    };
+    self.kinds.set(i, kind);
+  }
+
+  #[inline]
+  pub fn is_bridge(&self, i: usize) -> bool {
+    self.kinds.get(i) == BRIDGE
+  }
+
+  /// The cell's pipes split into channels, see `Tile::channels`.
+  #[inline]
+  pub fn channels(&self, i: usize) -> [Flag; 2] {
+    let kind = if self.is_bridge(i) {
+      TileKind::BRIDGE
+    } else {
+      TileKind::CONNECTOR
+    };
+    channels(kind, self.directions(i))
+  }
+
+  /// Index into `channels` of the channel the pipe `flag` belongs to.
+  #[inline]
+  pub fn channel(&self, i: usize, flag: Flag) -> usize {
+    if self.is_bridge(i) && (self.channels(i)[1] & flag) != 0 {
+      1
+    } else {
+      0
+    }
+  }
+
+  pub fn lock(&self, i: usize) -> Option<Lock> {
+    match self.locks.get(i) {
+      HINT => Some(Lock::Hint),
+      PLAYER => Some(Lock::Player),
+      _ => None,
+    }
+  }
+
+  pub fn set_lock(&mut self, i: usize, lock: Option<Lock>) {
+    let lock = match lock {
+      Some(Lock::Hint) => HINT,
+      Some(Lock::Player) => PLAYER,
+      None => 0,
+    };
+    self.locks.set(i, lock);
+  }
+
+  /// Makes room for colours up to `max`, clearing any set so far.
+  pub fn reserve_colors(&mut self, max: u8) {
+    self.colors = Packed::for_max(self.len, max as u64 + 1);
+  }
+
+  pub fn color(&self, i: usize) -> Option<u8> {
+    if self.colors.words.is_empty() {
+      return None;
+    }
+
+    match self.colors.get(i) {
+      0 => None,
+      c => Some((c - 1) as u8),
+    }
+  }
+
+  pub fn set_color(&mut self, i: usize, color: Option<u8>) {
+    self.colors.set(i, color.map_or(0, |c| c as u64 + 1));
+  }
+
+  /// The cell as a `Tile`, without its power.
+  pub fn tile(&self, i: usize) -> Tile {
+    Tile {
+      kind: self.kind(i),
+      directions: self.directions(i),
+      neighbours: self.neighbours(i),
+      powered: false,
+      servers: [None; 2],
+      color: self.color(i),
+      lock: self.lock(i),
+    }
+  }
+}
@@ -0,0 +1,74 @@
+use super::board::Board;
+use super::solver::{SolveStats, Solver};
+use serde::{Deserialize, Serialize};
+
+/// Named difficulty bands for generated puzzles.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Difficulty {
+  Easy,
+  Medium,
+  Hard,
+  Expert,
+}
+
+impl Difficulty {
+  /// The range of scores, lower bound inclusive, that counts as this
+  /// difficulty.
+  pub fn band(&self) -> (f32, f32) {
+    match self {
+      Difficulty::Easy => (0.0, 9.0),
+      Difficulty::Medium => (9.0, 13.0),
+      Difficulty::Hard => (13.0, 25.0),
+      Difficulty::Expert => (25.0, f32::INFINITY),
+    }
+  }
+
+  /// Board size used when a difficulty is asked for without one.
+  pub fn default_size(&self) -> (i32, i32) {
+    match self {
+      Difficulty::Easy => (5, 5),
+      Difficulty::Medium => (7, 7),
+      Difficulty::Hard => (10, 10),
+      Difficulty::Expert => (15, 15),
+    }
+  }
+
+  /// Distance from `score` to the band, zero inside it.
+  pub fn distance(&self, score: f32) -> f32 {
+    let (low, high) = self.band();
+    (low - score).max(score - high).max(0.0)
+  }
+}
+
+/// Largest board, in cells, that `Board::difficulty` rates. Solving takes
+/// far more time and memory than generating, so huge boards go unrated.
+pub const MAX_RATED_CELLS: usize = 200 * 200;
+
+/// Largest board, in cells, a difficulty can be asked for on. Finding one
+/// rates up to a thousand puzzles.
+pub const MAX_CHOSEN_CELLS: usize = 30 * 30;
+
+/// Rates a board from 0 to 100. Most of the score comes from the tiles that
+/// can't be placed by looking at their neighbours, more so if deduction
+/// can't place them at all; the rest grows with the number of guesses the
+/// solver needs and with how many ways the tiles can turn.
+pub fn score(board: &Board) -> f32 {
+  let stats = Solver::new(board).stats();
+  score_stats(&stats, board.get_topology().directions().len())
+}
+
+/// The score for solver `stats` on a grid with `directions` sides per tile.
+pub fn score_stats(stats: &SolveStats, directions: usize) -> f32 {
+  if stats.tiles == 0 {
+    return 0.0;
+  }
+
+  let tiles = stats.tiles as f32;
+  let global = 1.0 - stats.local as f32 / tiles;
+  let undeduced = 1.0 - stats.deduced as f32 / tiles;
+  let guesses = ((1.0 + stats.guesses as f32).log2() / 10.0).min(1.0);
+  let mix = (stats.orientations - 1.0) / (directions as f32 - 1.0);
+
+  100.0 * (0.45 * global + 0.25 * undeduced + 0.2 * guesses + 0.1 * mix)
+}
@@ -1,12 +1,18 @@
 use super::tile::TileOffset;
 use super::util::Flag;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[allow(non_camel_case_types)]
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
 pub enum DirectionKind {
   UP = 1,
   RIGHT = 2,
   DOWN = 4,
   LEFT = 8,
+  UP_RIGHT,
+  DOWN_RIGHT,
+  DOWN_LEFT,
+  UP_LEFT,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Default)]
@@ -15,6 +21,8 @@ pub struct Direction {
   pub flag: Flag,
   pub opposite: Flag,
   pub offset: TileOffset,
+  /// Offset used from odd rows, which hex boards shift half a tile right.
+  pub odd_offset: TileOffset,
   pub right: Flag,
   pub left: Flag,
 }
@@ -25,6 +33,27 @@ impl Default for DirectionKind {
   }
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Grid {
+  Square,
+  Hex,
+}
+
+impl Grid {
+  pub fn directions(&self) -> &'static [Direction] {
+    match self {
+      Grid::Square => &DIRECTIONS,
+      Grid::Hex => &HEX_DIRECTIONS,
+    }
+  }
+}
+
+impl Default for Grid {
+  fn default() -> Self {
+    Self::Square
+  }
+}
+
 pub static DIRECTIONS: [Direction; 4] = [
   Direction {
     kind: DirectionKind::UP,
@@ -33,6 +62,7 @@ pub static DIRECTIONS: [Direction; 4] = [
     right: 2,
     left: 8,
     offset: TileOffset { x: 0, y: -1 },
+    odd_offset: TileOffset { x: 0, y: -1 },
   },
   Direction {
     kind: DirectionKind::RIGHT,
@@ -41,6 +71,7 @@ pub static DIRECTIONS: [Direction; 4] = [
     right: 4,
     left: 1,
     offset: TileOffset { x: 1, y: 0 },
+    odd_offset: TileOffset { x: 1, y: 0 },
   },
   Direction {
     kind: DirectionKind::DOWN,
@@ -49,6 +80,7 @@ pub static DIRECTIONS: [Direction; 4] = [
     right: 8,
     left: 2,
     offset: TileOffset { x: 0, y: 1 },
+    odd_offset: TileOffset { x: 0, y: 1 },
   },
   Direction {
     kind: DirectionKind::LEFT,
@@ -57,5 +89,65 @@ pub static DIRECTIONS: [Direction; 4] = [
     right: 1,
     left: 4,
     offset: TileOffset { x: -1, y: 0 },
+    odd_offset: TileOffset { x: -1, y: 0 },
+  },
+];
+
+/// Pointy-top hexagons in clockwise order, with odd rows shifted half a tile
+/// to the right.
+pub static HEX_DIRECTIONS: [Direction; 6] = [
+  Direction {
+    kind: DirectionKind::UP_RIGHT,
+    flag: 1,
+    opposite: 8,
+    right: 2,
+    left: 32,
+    offset: TileOffset { x: 0, y: -1 },
+    odd_offset: TileOffset { x: 1, y: -1 },
+  },
+  Direction {
+    kind: DirectionKind::RIGHT,
+    flag: 2,
+    opposite: 16,
+    right: 4,
+    left: 1,
+    offset: TileOffset { x: 1, y: 0 },
+    odd_offset: TileOffset { x: 1, y: 0 },
+  },
+  Direction {
+    kind: DirectionKind::DOWN_RIGHT,
+    flag: 4,
+    opposite: 32,
+    right: 8,
+    left: 2,
+    offset: TileOffset { x: 0, y: 1 },
+    odd_offset: TileOffset { x: 1, y: 1 },
+  },
+  Direction {
+    kind: DirectionKind::DOWN_LEFT,
+    flag: 8,
+    opposite: 1,
+    right: 16,
+    left: 4,
+    offset: TileOffset { x: -1, y: 1 },
+    odd_offset: TileOffset { x: 0, y: 1 },
+  },
+  Direction {
+    kind: DirectionKind::LEFT,
+    flag: 16,
+    opposite: 2,
+    right: 32,
+    left: 8,
+    offset: TileOffset { x: -1, y: 0 },
+    odd_offset: TileOffset { x: -1, y: 0 },
+  },
+  Direction {
+    kind: DirectionKind::UP_LEFT,
+    flag: 32,
+    opposite: 4,
+    right: 1,
+    left: 16,
+    offset: TileOffset { x: -1, y: -1 },
+    odd_offset: TileOffset { x: 0, y: -1 },
   },
 ];
@@ -0,0 +1,262 @@
+/// Cells waiting to grow, in the order they were added. Finding and
+/// dropping the n-th one take logarithmic time, where a `Vec` would shift
+/// everything after it: the slots still in use are bits of a bitmap, and a
+/// Fenwick tree counts them a word at a time.
+///
+/// An unordered frontier drops a cell by moving the last one into its
+/// place instead, which takes constant time but picks other cells for the
+/// same draws.
+#[derive(Debug, Clone, Default)]
+pub struct Frontier {
+  cells: Vec<usize>,
+  unordered: bool,
+  live: Vec<u64>,
+  /// Fenwick tree over the number of bits set in each word of `live`,
+  /// one-based and sized to a power of two.
+  tree: Vec<u32>,
+  len: usize,
+}
+
+impl Frontier {
+  pub fn unordered() -> Self {
+    Self {
+      unordered: true,
+      ..Self::default()
+    }
+  }
+
+  pub fn len(&self) -> usize {
+    self.len
+  }
+
+  pub fn push(&mut self, cell: usize) {
+    if self.unordered {
+      self.cells.push(cell);
+      self.len += 1;
+      return;
+    }
+
+    let slot = self.cells.len();
+    if slot == 64 * self.live.len() {
+      self.rebuild();
+      return self.push(cell);
+    }
+
+    self.cells.push(cell);
+    self.live[slot / 64] |= 1 << (slot % 64);
+    self.add(slot / 64, 1);
+    self.len += 1;
+  }
+
+  /// The `n`-th cell.
+  pub fn get(&self, n: usize) -> usize {
+    if self.unordered {
+      return self.cells[n];
+    }
+    self.cells[self.slot(n)]
+  }
+
+  /// Drops the `n`-th cell.
+  pub fn remove(&mut self, n: usize) {
+    if self.unordered {
+      self.cells.swap_remove(n);
+      self.len -= 1;
+      return;
+    }
+
+    let slot = self.slot(n);
+    self.drop_slot(slot);
+  }
+
+  pub fn iter<'a>(&'a self) -> impl Iterator<Item = usize> + 'a {
+    (0..self.cells.len())
+      .filter(move |&slot| self.is_live(slot))
+      .map(move |slot| self.cells[slot])
+  }
+
+  /// Drops every cell for which `keep` is false.
+  pub fn retain<F: FnMut(usize) -> bool>(&mut self, mut keep: F) {
+    if self.unordered {
+      self.cells.retain(|&cell| keep(cell));
+      self.len = self.cells.len();
+      return;
+    }
+
+    for slot in 0..self.cells.len() {
+      if self.is_live(slot) && !keep(self.cells[slot]) {
+        self.drop_slot(slot);
+      }
+    }
+  }
+
+  fn is_live(&self, slot: usize) -> bool {
+    self.unordered || (self.live[slot / 64] & (1 << (slot % 64))) != 0
+  }
+
+  fn drop_slot(&mut self, slot: usize) {
+    self.live[slot / 64] &= !(1 << (slot % 64));
+    self.add(slot / 64, -1);
+    self.len -= 1;
+  }
+
+  fn add(&mut self, word: usize, delta: i32) {
+    let mut i = word + 1;
+    while i < self.tree.len() {
+      self.tree[i] = (self.tree[i] as i32 + delta) as u32;
+      i += i & i.wrapping_neg();
+    }
+  }
+
+  /// Slot of the `n`-th live cell.
+  fn slot(&self, n: usize) -> usize {
+    // The whole tree holds more than `n` cells, so the walk stays in its
+    // first half. Steps are taken without branching, as which way each
+    // one goes is a coin toss.
+    let mut word = 0;
+    let mut rest = n as u32;
+    let mut step = (self.tree.len() - 1) / 2;
+    while step > 0 {
+      let count = self.tree[word + step];
+      let take = (count <= rest) as usize;
+      word += step * take;
+      rest -= count * take as u32;
+      step >>= 1;
+    }
+
+    64 * word + select(self.live[word], rest) as usize
+  }
+
+  /// Packs the live cells into the first slots, making room for as many
+  /// again.
+  fn rebuild(&mut self) {
+    let cells: Vec<usize> = self.iter().collect();
+    let words = ((2 * cells.len() + 63) / 64).max(1).next_power_of_two();
+
+    self.live = vec![0; words];
+    for slot in 0..cells.len() {
+      self.live[slot / 64] |= 1 << (slot % 64);
+    }
+
+    self.tree = vec![0; words + 1];
+    for i in 1..=words {
+      self.tree[i] += self.live[i - 1].count_ones();
+      let parent = i + (i & i.wrapping_neg());
+      if parent <= words {
+        self.tree[parent] += self.tree[i];
+      }
+    }
+
+    self.cells = cells;
+  }
+}
+
+/// Position of the `n`-th set bit of `word`. Halves the word down to the
+/// bit without branching, like the walk down the tree in `slot`.
+fn select(word: u64, n: u32) -> u32 {
+  let mut word = word;
+  let mut n = n;
+  let mut base = 0;
+  for &half in [32, 16, 8, 4, 2, 1].iter() {
+    let low = (word & ((1 << half) - 1)).count_ones();
+    let take = (n >= low) as u32;
+    n -= low * take;
+    word >>= half * take;
+    base += half * take;
+  }
+  base
+}
+
+/// Marks an end's weight in `Weights`.
+const END: u32 = 1 << 31;
+
+/// Weights of cells, split between the ends of a tree and the rest. Either
+/// group can be drawn from in proportion to them, and changing a weight or
+/// drawing takes logarithmic time: like `Frontier`, each group has a Fenwick
+/// tree summing it 64 cells at a time.
+#[derive(Debug, Clone, Default)]
+pub struct Weights {
+  /// Each cell's weight, with `END` set for the ends.
+  weights: Vec<u32>,
+  ends: Vec<u64>,
+  others: Vec<u64>,
+}
+
+impl Weights {
+  pub fn new(len: usize) -> Self {
+    let blocks = ((len + 63) / 64).max(1).next_power_of_two();
+    Self {
+      weights: vec![0; len],
+      ends: vec![0; blocks + 1],
+      others: vec![0; blocks + 1],
+    }
+  }
+
+  pub fn get(&self, cell: usize) -> u32 {
+    self.weights[cell] & !END
+  }
+
+  /// Sets the weight of `cell`, which must stay below `END`, and whether
+  /// it's an end.
+  pub fn set(&mut self, cell: usize, end: bool, weight: u32) {
+    let old = self.weights[cell];
+    self.add(old & END != 0, cell / 64, -((old & !END) as i64));
+    self.add(end, cell / 64, weight as i64);
+    self.weights[cell] = if end { weight | END } else { weight };
+  }
+
+  /// Total weight of the ends, or of the rest.
+  pub fn total(&self, end: bool) -> u64 {
+    let tree = self.tree(end);
+    tree[tree.len() - 1]
+  }
+
+  /// The cell the `n`-th unit of weight of the ends, or of the rest, falls
+  /// in, and how far into its weight.
+  pub fn find(&self, end: bool, n: u64) -> (usize, u64) {
+    let tree = self.tree(end);
+    let mut block = 0;
+    let mut rest = n;
+    let mut step = (tree.len() - 1) / 2;
+    while step > 0 {
+      if tree[block + step] <= rest {
+        rest -= tree[block + step];
+        block += step;
+      }
+      step >>= 1;
+    }
+
+    // The tree's block holds more than `rest`, so some cell in it does.
+    (64 * block..self.weights.len())
+      .filter(|&cell| (self.weights[cell] & END != 0) == end)
+      .find_map(|cell| {
+        let weight = self.get(cell) as u64;
+        if rest < weight {
+          return Some((cell, rest));
+        }
+        rest -= weight;
+        None
+      })
+      .unwrap()
+  }
+
+  fn tree(&self, end: bool) -> &Vec<u64> {
+    if end {
+      &self.ends
+    } else {
+      &self.others
+    }
+  }
+
+  fn add(&mut self, end: bool, block: usize, delta: i64) {
+    let tree = if end {
+      &mut self.ends
+    } else {
+      &mut self.others
+    };
+    let mut i = block + 1;
+    while i < tree.len() {
+      tree[i] = (tree[i] as i64 + delta) as u64;
+      i += i & i.wrapping_neg();
+    }
+  }
+}
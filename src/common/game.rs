@@ -1,21 +1,245 @@
-use super::board::Board;
+use super::board::{Board, BoardOptions, InvalidOptionsError};
+use super::difficulty::{self, Difficulty};
+use super::history::{History, Move};
+use super::puzzle::PuzzleId;
+use super::replay::{InvalidReplayError, LoggedMove, MoveKind, Replay, REPLAY_VERSION};
 use super::rng::{GameRng, GameSeed};
+use super::tile::{Rotation, TilePos};
 use std::fmt;
 
+/// How many seeds to try for a difficulty before settling for the closest.
+const DIFFICULTY_ATTEMPTS: usize = 1000;
+
+/// Rotations, as a multiple of par, that still earn two stars.
+const TWO_STARS: usize = 2;
+
 pub struct Game {
   pub rng: GameRng,
   pub board: Board,
+  pub options: BoardOptions,
+  history: History,
+  /// Every rotation made, undos and redos included.
+  moves: Vec<LoggedMove>,
+  /// See `rotations`.
+  rotations: usize,
+  /// See `difficulty`.
+  difficulty: Option<f32>,
 }
 
 impl Game {
   pub fn new(width: i32, height: i32, seed: GameSeed) -> Self {
+    Self::with_options(width, height, seed, &BoardOptions::default()).unwrap()
+  }
+
+  pub fn with_options(
+    width: i32,
+    height: i32,
+    seed: GameSeed,
+    options: &BoardOptions,
+  ) -> Result<Self, InvalidOptionsError> {
+    let mut game = Self::unrated(width, height, seed, options)?;
+    game.difficulty = game.board.difficulty();
+    Ok(game)
+  }
+
+  /// A game left unrated, as rating solves the whole puzzle.
+  fn unrated(
+    width: i32,
+    height: i32,
+    seed: GameSeed,
+    options: &BoardOptions,
+  ) -> Result<Self, InvalidOptionsError> {
     let mut rng = GameRng::from(seed);
-    let mut board = Board::new(width, height, &mut rng);
+    let mut board = Board::with_options(width, height, options, &mut rng)?;
+    if let Some(hints) = options.hints {
+      board.lock_hints(hints, &mut rng);
+    }
     board.scramble(&mut rng);
 
-    Self {
+    Ok(Self {
       rng: rng,
       board: board,
+      options: options.clone(),
+      history: History::default(),
+      moves: Vec::new(),
+      rotations: 0,
+      difficulty: None,
+    })
+  }
+
+  /// Draws seeds until the puzzle scores within `difficulty`'s band. Small
+  /// boards may never get hard enough, so after a bounded number of tries
+  /// the closest puzzle found is used instead. Every try solves its puzzle,
+  /// so boards over `difficulty::MAX_CHOSEN_CELLS` are refused.
+  pub fn with_difficulty(
+    width: i32,
+    height: i32,
+    difficulty: Difficulty,
+    options: &BoardOptions,
+  ) -> Result<Self, InvalidOptionsError> {
+    match width.checked_mul(height) {
+      Some(cells) if cells >= 0 && cells as usize <= difficulty::MAX_CHOSEN_CELLS => {}
+      _ => return Err(InvalidOptionsError),
+    }
+
+    let mut best: Option<(f32, Self)> = None;
+    for _ in 0..DIFFICULTY_ATTEMPTS {
+      let mut game = Self::unrated(width, height, GameSeed::new(), options)?;
+      let score = difficulty::score(&game.board);
+      game.difficulty = Some(score);
+
+      let distance = difficulty.distance(score);
+      if distance == 0.0 {
+        return Ok(game);
+      }
+
+      if best.as_ref().map_or(true, |(d, _)| distance < *d) {
+        best = Some((distance, game));
+      }
+    }
+
+    Ok(best.unwrap().1)
+  }
+
+  pub fn from_puzzle_id(id: &PuzzleId) -> Result<Self, InvalidOptionsError> {
+    Self::with_options(id.size.width, id.size.height, id.seed.clone(), &id.options)
+  }
+
+  /// Turns the tile at `pos`, remembering the move so it can be undone
+  /// unless it changed nothing. `None` if the tile can't be turned so, see
+  /// `Board::rotate_tile`.
+  pub fn rotate_tile(&mut self, pos: &TilePos, rotation: Rotation) -> Option<Move> {
+    let (from, to) = self.board.rotate_tile(pos, rotation)?;
+    let m = Move {
+      pos: *pos,
+      rotation: rotation,
+      from: from,
+      to: to,
+    };
+    if !m.is_no_op() {
+      self.history.push(m);
+      self.log(MoveKind::Turn, m);
+    }
+
+    Some(m)
+  }
+
+  /// Takes back the last move, returning the move that did. Nothing happens
+  /// if that tile has been locked since.
+  pub fn undo(&mut self) -> Option<Move> {
+    let last = *self.history.last_done()?;
+    let rotation = last.inverse();
+    let (from, to) = self.board.rotate_tile(&last.pos, rotation)?;
+
+    let m = Move {
+      pos: last.pos,
+      rotation: rotation,
+      from: from,
+      to: to,
+    };
+    self.history.undo();
+    self.log(MoveKind::Undo, m);
+    Some(m)
+  }
+
+  /// Makes the last undone move again, see `undo`.
+  pub fn redo(&mut self) -> Option<Move> {
+    let last = *self.history.last_undone()?;
+    let (from, to) = self.board.rotate_tile(&last.pos, last.rotation)?;
+
+    let m = Move {
+      from: from,
+      to: to,
+      ..last
+    };
+    self.history.redo();
+    self.log(MoveKind::Redo, m);
+    Some(m)
+  }
+
+  fn log(&mut self, kind: MoveKind, m: Move) {
+    self.rotations += self.board.turns(m.from, m.to).unwrap();
+    self.moves.push(LoggedMove {
+      kind: kind,
+      pos: m.pos,
+      rotation: m.rotation,
+      time: self.board.get_start_time().elapsed().as_millis() as u64,
+    });
+  }
+
+  /// How hard the puzzle is, from 0 to 100, see `Board::difficulty`. Rated
+  /// once as the game is made, and `None` for games played back from a
+  /// replay.
+  pub fn difficulty(&self) -> Option<f32> {
+    self.difficulty
+  }
+
+  /// Moves undone in this game so far. A clean run has none.
+  pub fn undos(&self) -> usize {
+    self.history.undos()
+  }
+
+  /// Plays a replay's moves on its puzzle, keeping their times. Fails if
+  /// any of them can't be made as recorded.
+  pub fn from_replay(replay: &Replay) -> Result<Self, InvalidReplayError> {
+    let id = PuzzleId::from_str(&replay.puzzle).map_err(|_| InvalidReplayError)?;
+    let mut game = Self::unrated(id.size.width, id.size.height, id.seed, &id.options)
+      .map_err(|_| InvalidReplayError)?;
+
+    for m in &replay.moves {
+      let made = match m.kind {
+        MoveKind::Turn => game.rotate_tile(&m.pos, m.rotation),
+        MoveKind::Undo => game.undo(),
+        MoveKind::Redo => game.redo(),
+      };
+      // Turns that change nothing aren't logged, so can't be replayed.
+      if made.map_or(true, |made| made.is_no_op()) {
+        return Err(InvalidReplayError);
+      }
+
+      // Undos and redos pick their own tile, which has to be the recorded
+      // one.
+      let last = game.moves.last_mut().unwrap();
+      if last.pos != m.pos || last.rotation != m.rotation {
+        return Err(InvalidReplayError);
+      }
+      last.time = m.time;
+    }
+
+    Ok(game)
+  }
+
+  /// Steps tiles were turned so far, undos and redos included. A half turn
+  /// counts as the steps it takes, and a turn straight to some pipes as the
+  /// fewest that get there, so that it compares with par.
+  pub fn rotations(&self) -> usize {
+    self.rotations
+  }
+
+  /// Three stars for rotations within par, two within `TWO_STARS` times
+  /// par, one otherwise.
+  pub fn stars(&self) -> u8 {
+    let par = self.board.get_par();
+    match self.rotations() {
+      n if n <= par => 3,
+      n if n <= TWO_STARS * par => 2,
+      _ => 1,
+    }
+  }
+
+  pub fn replay(&self) -> Replay {
+    Replay {
+      version: REPLAY_VERSION,
+      puzzle: self.puzzle_id().to_string(),
+      moves: self.moves.clone(),
+    }
+  }
+
+  pub fn puzzle_id(&self) -> PuzzleId {
+    PuzzleId {
+      size: self.board.get_size(),
+      seed: self.rng.seed().clone(),
+      options: self.options.clone(),
     }
   }
 }
@@ -0,0 +1,288 @@
+use super::direction::Direction;
+use super::rng::GameRng;
+use super::tile::TilePos;
+use super::topology::Topology;
+use super::util::{find, BitFlag, Flag};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// How the spanning tree behind a puzzle is built.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Algorithm {
+  /// Randomized Prim: grows from a random frontier tile each step, giving
+  /// short, bushy branches.
+  Prim,
+  /// Loop-erased random walks, which pick every spanning tree with equal
+  /// chance.
+  Wilson,
+  /// Recursive backtracker: digs as far as it can before branching, giving
+  /// long corridors.
+  Backtracker,
+  /// Joins the cells over randomly ordered edges.
+  Kruskal,
+}
+
+/// Builds a spanning forest with one tree per root and returns the pipes of
+/// every cell.
+pub type Builder = fn(&dyn Topology, &[TilePos], &mut GameRng) -> Vec<Flag>;
+
+impl Algorithm {
+  /// The builder for the algorithm, or `None` for `Prim`, which the board
+  /// grows itself.
+  pub fn builder(&self) -> Option<Builder> {
+    match self {
+      Algorithm::Prim => None,
+      Algorithm::Wilson => Some(wilson),
+      Algorithm::Backtracker => Some(backtracker),
+      Algorithm::Kruskal => Some(kruskal),
+    }
+  }
+}
+
+impl Default for Algorithm {
+  fn default() -> Self {
+    Self::Prim
+  }
+}
+
+/// Where the first server goes. Any further servers are placed at random.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum RootPlacement {
+  Random,
+  Center,
+  Corner,
+}
+
+impl Default for RootPlacement {
+  fn default() -> Self {
+    Self::Random
+  }
+}
+
+/// Weight of the heaviest step, see `TileWeights::step`.
+pub const STEP_SCALE: f32 = 65536.0;
+
+/// Relative weights of the tile shapes the generator grows the tree into.
+/// Hex tiles with five or six pipes count as crosses.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct TileWeights {
+  pub straight: f32,
+  pub corner: f32,
+  pub tee: f32,
+  pub cross: f32,
+}
+
+impl Default for TileWeights {
+  fn default() -> Self {
+    Self {
+      straight: 1.0,
+      corner: 1.0,
+      tee: 1.0,
+      cross: 1.0,
+    }
+  }
+}
+
+impl TileWeights {
+  /// Weight of a tile whose pipes are `flag`.
+  pub fn weight(&self, flag: Flag, directions: &[Direction]) -> f32 {
+    match flag.count_bits() {
+      0 | 1 => 1.0,
+      2 => {
+        if directions.iter().any(|d| d.flag | d.opposite == flag) {
+          self.straight
+        } else {
+          self.corner
+        }
+      }
+      3 => self.tee,
+      _ => self.cross,
+    }
+  }
+
+  /// Weight of growing a tile with pipes `flag` by the pipe `dir`, as a
+  /// whole number where the heaviest shape weighs `STEP_SCALE`. Even the
+  /// lightest steps weigh one, as they're a last resort rather than
+  /// impossible, or the tree couldn't span the board.
+  pub fn step(&self, flag: Flag, dir: &Direction, directions: &[Direction]) -> u32 {
+    let heaviest = [self.straight, self.corner, self.tee, self.cross]
+      .iter()
+      .fold(1.0, |a: f32, &b| a.max(b));
+    let weight = self.weight(flag | dir.flag, directions) / heaviest;
+    (weight * STEP_SCALE).round().max(1.0) as u32
+  }
+}
+
+/// Controls for the shape of the generated tree. The default grows it
+/// uniformly at random, as the generator always has.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(default)]
+pub struct GeneratorConfig {
+  pub algorithm: Algorithm,
+  /// Tile weights, the terminal ratio and bridges only steer `Prim`.
+  pub tiles: Option<TileWeights>,
+  /// Share of the tiles to end up as terminals, from 0 to 1. The generator
+  /// steers towards it but doesn't hit it exactly.
+  pub terminals: Option<f32>,
+  pub root: RootPlacement,
+}
+
+impl GeneratorConfig {
+  /// Whether the tree is grown by weighing every possible step instead of
+  /// the plain random walk.
+  pub fn is_weighted(&self) -> bool {
+    self.tiles.is_some() || self.terminals.is_some()
+  }
+
+  /// How much likelier a step from an end of the tree is than one from any
+  /// other tile, and the other way round, given the share of terminals
+  /// among the `visited` tiles so far. Extending a terminal keeps the
+  /// number of terminals, branching off anything else adds one.
+  pub fn end_bias(&self, terminals: usize, visited: usize) -> (u64, u64) {
+    match self.terminals {
+      Some(target) if terminals as f32 > target * visited as f32 => (16, 1),
+      Some(_) => (1, 16),
+      None => (1, 1),
+    }
+  }
+}
+
+/// Builds a spanning forest using Wilson's algorithm.
+pub fn wilson(topology: &dyn Topology, roots: &[TilePos], rng: &mut GameRng) -> Vec<Flag> {
+  let directions = topology.directions();
+  let mut flags = vec![0; topology.len()];
+  let mut in_tree = vec![false; topology.len()];
+  for pos in roots {
+    in_tree[topology.index(pos)] = true;
+  }
+
+  // Direction last taken out of each cell; later visits overwrite earlier
+  // ones, which erases the loops of the walk.
+  let mut exits = vec![0; topology.len()];
+  for i in 0..topology.len() {
+    let start = topology.pos(i);
+    if in_tree[i] || !topology.contains(&start) {
+      continue;
+    }
+
+    let mut pos = start;
+    while !in_tree[topology.index(&pos)] {
+      let open = open_directions(topology, &pos);
+      let d = open[rng.gen_range(0, open.len())];
+      exits[topology.index(&pos)] = d;
+      pos = topology.neighbour(&pos, &directions[d]).unwrap();
+    }
+
+    let mut pos = start;
+    while !in_tree[topology.index(&pos)] {
+      let i = topology.index(&pos);
+      in_tree[i] = true;
+      pos = link(topology, &mut flags, &pos, &directions[exits[i]]);
+    }
+  }
+
+  flags
+}
+
+/// Builds a spanning forest by depth-first search, advancing a randomly
+/// chosen tree each step.
+pub fn backtracker(topology: &dyn Topology, roots: &[TilePos], rng: &mut GameRng) -> Vec<Flag> {
+  let directions = topology.directions();
+  let mut flags = vec![0; topology.len()];
+  let mut visited = vec![false; topology.len()];
+  let mut stacks: Vec<Vec<TilePos>> = roots.iter().map(|pos| vec![*pos]).collect();
+  for pos in roots {
+    visited[topology.index(pos)] = true;
+  }
+
+  loop {
+    stacks.retain(|s| !s.is_empty());
+    if stacks.is_empty() {
+      break;
+    }
+
+    let s = rng.gen_range(0, stacks.len());
+    let pos = *stacks[s].last().unwrap();
+    let free: Vec<usize> = open_directions(topology, &pos)
+      .into_iter()
+      .filter(|&d| {
+        let next = topology.neighbour(&pos, &directions[d]).unwrap();
+        !visited[topology.index(&next)]
+      })
+      .collect();
+
+    if free.is_empty() {
+      stacks[s].pop();
+      continue;
+    }
+
+    let d = free[rng.gen_range(0, free.len())];
+    let next = link(topology, &mut flags, &pos, &directions[d]);
+    visited[topology.index(&next)] = true;
+    stacks[s].push(next);
+  }
+
+  flags
+}
+
+/// Builds a spanning forest using Kruskal's algorithm, never joining two
+/// trees that both hold a root.
+pub fn kruskal(topology: &dyn Topology, roots: &[TilePos], rng: &mut GameRng) -> Vec<Flag> {
+  let directions = topology.directions();
+  let mut edges = Vec::new();
+  for i in 0..topology.len() {
+    let pos = topology.pos(i);
+    if !topology.contains(&pos) {
+      continue;
+    }
+
+    for d in open_directions(topology, &pos) {
+      let next = topology.neighbour(&pos, &directions[d]).unwrap();
+      if topology.index(&next) > i {
+        edges.push((pos, d, topology.index(&next)));
+      }
+    }
+  }
+
+  for i in (1..edges.len()).rev() {
+    edges.swap(i, rng.gen_range(0, i + 1));
+  }
+
+  let mut flags = vec![0; topology.len()];
+  let mut parent: Vec<usize> = (0..topology.len()).collect();
+  let mut rooted = vec![false; topology.len()];
+  for pos in roots {
+    rooted[topology.index(pos)] = true;
+  }
+
+  for (pos, d, b) in edges {
+    let ra = find(&mut parent, topology.index(&pos));
+    let rb = find(&mut parent, b);
+    if ra == rb || (rooted[ra] && rooted[rb]) {
+      continue;
+    }
+
+    parent[ra] = rb;
+    rooted[rb] |= rooted[ra];
+    link(topology, &mut flags, &pos, &directions[d]);
+  }
+
+  flags
+}
+
+/// Indices of the directions of `pos` that lead to another cell.
+fn open_directions(topology: &dyn Topology, pos: &TilePos) -> Vec<usize> {
+  let directions = topology.directions();
+  (0..directions.len())
+    .filter(|&d| topology.neighbour(pos, &directions[d]).is_some())
+    .collect()
+}
+
+/// Connects `pos` to its neighbour in `dir` and returns the neighbour.
+fn link(topology: &dyn Topology, flags: &mut [Flag], pos: &TilePos, dir: &Direction) -> TilePos {
+  let next = topology.neighbour(pos, dir).unwrap();
+  flags[topology.index(pos)] |= dir.flag;
+  flags[topology.index(&next)] |= dir.opposite;
+  next
+}
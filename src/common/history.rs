@@ -0,0 +1,81 @@
+use super::tile::{Rotation, TilePos};
+use super::util::Flag;
+use std::collections::VecDeque;
+
+/// How many moves back a game can be undone.
+pub const MAX_UNDO: usize = 100;
+
+/// A turn of a tile, as the player asked for it and how it came out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Move {
+  pub pos: TilePos,
+  pub rotation: Rotation,
+  /// The tile's pipes before and after.
+  pub from: Flag,
+  pub to: Flag,
+}
+
+impl Move {
+  /// Whether the tile looks the same after, so nothing really moved.
+  pub fn is_no_op(&self) -> bool {
+    self.from == self.to
+  }
+
+  /// The rotation that takes this move back.
+  pub fn inverse(&self) -> Rotation {
+    match self.rotation {
+      Rotation::Right => Rotation::Left,
+      Rotation::Left => Rotation::Right,
+      Rotation::Half => Rotation::Half,
+      Rotation::To(_) => Rotation::To(self.from),
+    }
+  }
+}
+
+/// Moves that can be undone and redone. Only the last `MAX_UNDO` are kept,
+/// and making a new move forgets the undone ones.
+#[derive(Debug, Clone, Default)]
+pub struct History {
+  done: VecDeque<Move>,
+  /// Undone moves, the last one undone last.
+  undone: Vec<Move>,
+  /// Times a move was undone, whether or not it was redone since.
+  undos: usize,
+}
+
+impl History {
+  pub fn push(&mut self, m: Move) {
+    if self.done.len() == MAX_UNDO {
+      self.done.pop_front();
+    }
+    self.done.push_back(m);
+    self.undone.clear();
+  }
+
+  /// The move `undo` would take back.
+  pub fn last_done(&self) -> Option<&Move> {
+    self.done.back()
+  }
+
+  /// The move `redo` would make again.
+  pub fn last_undone(&self) -> Option<&Move> {
+    self.undone.last()
+  }
+
+  pub fn undo(&mut self) -> Option<Move> {
+    let m = self.done.pop_back()?;
+    self.undone.push(m);
+    self.undos += 1;
+    Some(m)
+  }
+
+  pub fn redo(&mut self) -> Option<Move> {
+    let m = self.undone.pop()?;
+    self.done.push_back(m);
+    Some(m)
+  }
+
+  pub fn undos(&self) -> usize {
+    self.undos
+  }
+}
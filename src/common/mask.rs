@@ -0,0 +1,232 @@
+use super::tile::TilePos;
+use super::util::Size;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fs;
+use std::path::Path;
+
+/// Where a board's shape comes from.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum MaskSource {
+  /// One of the built-in shapes, scaled to the requested board size.
+  Named(String),
+  /// ASCII art, see `Mask::from_ascii`.
+  Ascii(String),
+  /// A PBM image, hex encoded so raw ones fit in JSON too. See
+  /// `Mask::from_pbm`.
+  Pbm(String),
+}
+
+pub static MASK_NAMES: [&str; 4] = ["circle", "cross", "diamond", "ring"];
+
+/// Most cells a mask can have, as big as the biggest boards generated.
+pub const MAX_MASK_CELLS: usize = 4096 * 4096;
+
+#[derive(Debug)]
+pub struct InvalidMaskError;
+
+/// Number of cells in a `width` x `height` mask, if it's a valid size.
+fn cell_count(width: i32, height: i32) -> Result<usize, InvalidMaskError> {
+  if width <= 0 || height <= 0 {
+    return Err(InvalidMaskError);
+  }
+
+  match width.checked_mul(height) {
+    Some(n) if n as usize <= MAX_MASK_CELLS => Ok(n as usize),
+    _ => Err(InvalidMaskError),
+  }
+}
+
+/// Marks which cells of a `size` rectangle are part of the board.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mask {
+  size: Size<i32>,
+  cells: Vec<bool>,
+}
+
+impl Mask {
+  pub fn new(size: Size<i32>, cells: Vec<bool>) -> Result<Self, InvalidMaskError> {
+    if cells.len() != cell_count(size.width, size.height)? || !cells.iter().any(|&c| c) {
+      return Err(InvalidMaskError);
+    }
+
+    Ok(Self {
+      size: size,
+      cells: cells,
+    })
+  }
+
+  pub fn from_source(
+    source: &MaskSource,
+    width: i32,
+    height: i32,
+  ) -> Result<Self, InvalidMaskError> {
+    match source {
+      MaskSource::Named(name) => Self::named(name, width, height),
+      MaskSource::Ascii(s) => Self::from_ascii(s),
+      MaskSource::Pbm(s) => Self::from_pbm(&hex::decode(s).map_err(|_| InvalidMaskError)?),
+    }
+  }
+
+  /// Builds one of `MASK_NAMES` to fill a `width` x `height` board.
+  pub fn named(name: &str, width: i32, height: i32) -> Result<Self, InvalidMaskError> {
+    let rx = width as f32 / 2.0;
+    let ry = height as f32 / 2.0;
+
+    let f: fn(f32, f32) -> bool = match name {
+      "circle" => |x, y| x * x + y * y <= 1.0,
+      "cross" => |x, y| x.abs() <= 1.0 / 3.0 || y.abs() <= 1.0 / 3.0,
+      "diamond" => |x, y| x.abs() + y.abs() <= 1.0,
+      "ring" => |x, y| {
+        let d = x * x + y * y;
+        d <= 1.0 && d >= 0.25
+      },
+      _ => return Err(InvalidMaskError),
+    };
+
+    let mut cells = Vec::with_capacity(cell_count(width, height)?);
+    for y in 0..height {
+      for x in 0..width {
+        let dx = (x as f32 + 0.5 - rx) / rx;
+        let dy = (y as f32 + 0.5 - ry) / ry;
+        cells.push(f(dx, dy));
+      }
+    }
+
+    Self::new(Size::new(width, height), cells)
+  }
+
+  /// Reads a PBM image if the file starts with a `P1` or `P4` header and
+  /// ASCII art otherwise.
+  pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, InvalidMaskError> {
+    let data = fs::read(path).map_err(|_| InvalidMaskError)?;
+    if data.starts_with(b"P1") || data.starts_with(b"P4") {
+      Self::from_pbm(&data)
+    } else {
+      let s = String::from_utf8(data).map_err(|_| InvalidMaskError)?;
+      Self::from_ascii(&s)
+    }
+  }
+
+  /// One line per row. `.`, `0` and spaces are holes and any other character
+  /// is a cell; short rows are padded with holes.
+  pub fn from_ascii(s: &str) -> Result<Self, InvalidMaskError> {
+    let mut rows: Vec<&str> = s.lines().map(|l| l.trim_end()).collect();
+    while rows.last().map_or(false, |r| r.is_empty()) {
+      rows.pop();
+    }
+
+    let width = rows.iter().map(|r| r.chars().count()).max().unwrap_or(0);
+    let width = i32::try_from(width).map_err(|_| InvalidMaskError)?;
+    let height = i32::try_from(rows.len()).map_err(|_| InvalidMaskError)?;
+    let mut cells = Vec::with_capacity(cell_count(width, height)?);
+    for row in rows.iter() {
+      let mut n = 0;
+      for c in row.chars() {
+        cells.push(match c {
+          '.' | '0' | ' ' => false,
+          _ => true,
+        });
+        n += 1;
+      }
+      cells.extend((n..width).map(|_| false));
+    }
+
+    Self::new(Size::new(width, height), cells)
+  }
+
+  /// Plain (`P1`) or raw (`P4`) bitmap where black pixels are cells.
+  pub fn from_pbm(data: &[u8]) -> Result<Self, InvalidMaskError> {
+    let mut i = 0;
+    let magic = pbm_token(data, &mut i).ok_or(InvalidMaskError)?;
+    let width = pbm_number(data, &mut i)?;
+    let height = pbm_number(data, &mut i)?;
+    let n = cell_count(width, height)?;
+
+    let cells = match magic {
+      b"P1" => {
+        let mut cells = Vec::with_capacity(n);
+        while cells.len() < n {
+          pbm_skip(data, &mut i);
+          match data.get(i) {
+            Some(b'0') => cells.push(false),
+            Some(b'1') => cells.push(true),
+            _ => return Err(InvalidMaskError),
+          }
+          i += 1;
+        }
+        cells
+      }
+      b"P4" => {
+        let row_len = ((width + 7) / 8) as usize;
+        let bits = data.get(i + 1..).ok_or(InvalidMaskError)?;
+        if bits.len() < row_len * height as usize {
+          return Err(InvalidMaskError);
+        }
+
+        let mut cells = Vec::with_capacity(n);
+        for y in 0..height as usize {
+          for x in 0..width as usize {
+            cells.push(bits[y * row_len + x / 8] & (0x80 >> (x % 8)) != 0);
+          }
+        }
+        cells
+      }
+      _ => return Err(InvalidMaskError),
+    };
+
+    Self::new(Size::new(width, height), cells)
+  }
+
+  pub fn get_size(&self) -> Size<i32> {
+    self.size
+  }
+
+  pub fn contains(&self, pos: &TilePos) -> bool {
+    pos.x >= 0
+      && pos.y >= 0
+      && pos.x < self.size.width
+      && pos.y < self.size.height
+      && self.cells[(pos.x + pos.y * self.size.width) as usize]
+  }
+
+  pub fn get_cells(&self) -> &[bool] {
+    &self.cells
+  }
+}
+
+fn pbm_skip(data: &[u8], i: &mut usize) {
+  while *i < data.len() {
+    match data[*i] {
+      b'#' => {
+        while *i < data.len() && data[*i] != b'\n' {
+          *i += 1;
+        }
+      }
+      c if c.is_ascii_whitespace() => *i += 1,
+      _ => return,
+    }
+  }
+}
+
+fn pbm_token<'a>(data: &'a [u8], i: &mut usize) -> Option<&'a [u8]> {
+  pbm_skip(data, i);
+  let start = *i;
+  while *i < data.len() && !data[*i].is_ascii_whitespace() {
+    *i += 1;
+  }
+
+  if start == *i {
+    None
+  } else {
+    Some(&data[start..*i])
+  }
+}
+
+fn pbm_number(data: &[u8], i: &mut usize) -> Result<i32, InvalidMaskError> {
+  let token = pbm_token(data, i).ok_or(InvalidMaskError)?;
+  std::str::from_utf8(token)
+    .ok()
+    .and_then(|s| s.parse().ok())
+    .ok_or(InvalidMaskError)
+}
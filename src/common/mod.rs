@@ -1,6 +1,19 @@
 pub mod board;
+pub mod cells;
+pub mod difficulty;
 pub mod direction;
+pub mod frontier;
 pub mod game;
+pub mod generator;
+pub mod history;
+pub mod mask;
+pub mod power;
+pub mod puzzle;
+pub mod replay;
 pub mod rng;
+pub mod rules;
+pub mod solver;
 pub mod tile;
+pub mod topology;
 pub mod util;
+pub mod verify;
@@ -0,0 +1,466 @@
+use super::cells::{Cells, Packed};
+use super::rules::WinRule;
+use super::tile::{TileKind, TilePos};
+use super::topology::Topology;
+use super::util::{find, Flag};
+use std::collections::HashMap;
+
+/// Which server powers each channel of the board, kept up to date as tiles
+/// turn so a move only walks the networks it changes.
+///
+/// Channels are numbered `2 * tile + channel`, see `Tile::channels`. Only
+/// bridges have a second channel, so those are kept apart from the rest.
+#[derive(Debug, Clone, Default)]
+pub struct Power {
+  /// Server plus one powering the first channel of each tile, zero for
+  /// none, in as few bits as the number of servers allows.
+  servers: Packed,
+  /// Server powering the second channel of each powered bridge.
+  crossings: HashMap<usize, usize>,
+  /// Servers each server's network runs into.
+  touching: Vec<Vec<usize>>,
+  /// Channels with pipes that no server powers.
+  unpowered: usize,
+  /// Terminals that no server powers.
+  unpowered_terminals: usize,
+  /// Terminals powered by a server of another colour.
+  wrong_colors: usize,
+  /// Channels changed since the changes were last taken, with the server
+  /// each had before, and a bit for each channel telling whether it's
+  /// among them.
+  changed: Vec<(usize, Option<usize>)>,
+  is_changed: Vec<u64>,
+}
+
+impl Power {
+  /// Index into the board's roots of the server powering channel `node`.
+  pub fn server(&self, node: usize) -> Option<usize> {
+    let server = if node % 2 == 0 {
+      self.servers.get(node / 2) as usize
+    } else {
+      self.crossings.get(&(node / 2)).map_or(0, |s| s + 1)
+    };
+    server.checked_sub(1)
+  }
+
+  /// Channels whose server changed since this was last called, with the
+  /// server each has now, in order.
+  pub fn take_changes(&mut self) -> Vec<(usize, Option<usize>)> {
+    for &(node, _) in &self.changed {
+      self.is_changed[node / 64] &= !(1 << (node % 64));
+    }
+
+    // A channel walked again may end up with the server it had.
+    let changed = std::mem::replace(&mut self.changed, Vec::new());
+    let mut changes: Vec<(usize, Option<usize>)> = changed
+      .into_iter()
+      .map(|(node, old)| (node, old, self.server(node)))
+      .filter(|&(_, old, server)| server != old)
+      .map(|(node, _, server)| (node, server))
+      .collect();
+    changes.sort();
+    changes
+  }
+
+  /// Whether any two networks are connected.
+  pub fn touches(&self) -> bool {
+    self.touching.iter().any(|t| !t.is_empty())
+  }
+
+  /// Whether the board is solved under `rule`. With the pipes the generator
+  /// lays, powering every channel already rules out loops and open ends,
+  /// so the classic rule needs nothing more than `Powered`.
+  pub fn is_solved(&self, rule: WinRule) -> bool {
+    let unpowered = match rule {
+      WinRule::Terminals => self.unpowered_terminals,
+      WinRule::Powered | WinRule::Classic => self.unpowered,
+    };
+
+    unpowered == 0 && self.wrong_colors == 0 && !self.touches()
+  }
+}
+
+/// Searches through the pieces of a cut network, merged as they meet.
+struct Pieces {
+  owner: Vec<usize>,
+  stacks: Vec<Vec<usize>>,
+  nodes: Vec<Vec<usize>>,
+  /// Whether the piece holds the server.
+  rooted: Vec<bool>,
+  /// Search that reached each channel first.
+  seen: HashMap<usize, usize>,
+  root: usize,
+}
+
+impl Pieces {
+  fn is_active(&self, j: usize) -> bool {
+    self.owner[j] == j && !self.rooted[j] && !self.stacks[j].is_empty()
+  }
+
+  /// Adds `node` to search `j`, or merges the two searches if another one
+  /// got there first.
+  fn reach(&mut self, node: usize, j: usize) {
+    let j = find(&mut self.owner, j);
+    let by = match self.seen.get(&node) {
+      Some(&by) => by,
+      None => {
+        self.seen.insert(node, j);
+        self.stacks[j].push(node);
+        self.nodes[j].push(node);
+        self.rooted[j] |= node == self.root;
+        return;
+      }
+    };
+
+    let other = find(&mut self.owner, by);
+    if other != j {
+      self.owner[other] = j;
+      self.rooted[j] |= self.rooted[other];
+      let mut stack = std::mem::replace(&mut self.stacks[other], Vec::new());
+      self.stacks[j].append(&mut stack);
+      let mut nodes = std::mem::replace(&mut self.nodes[other], Vec::new());
+      self.nodes[j].append(&mut nodes);
+    }
+  }
+}
+
+/// Channels next to a tile, at most one in each direction.
+#[derive(Clone, Copy)]
+struct Links {
+  nodes: [usize; 6],
+  len: usize,
+}
+
+impl Links {
+  fn new() -> Self {
+    Self {
+      nodes: [0; 6],
+      len: 0,
+    }
+  }
+
+  fn push(&mut self, node: usize) {
+    self.nodes[self.len] = node;
+    self.len += 1;
+  }
+
+  fn as_slice(&self) -> &[usize] {
+    &self.nodes[..self.len]
+  }
+}
+
+/// A board's tiles seen as the channels power flows through.
+pub struct PowerGrid<'a> {
+  pub cells: &'a Cells,
+  pub topology: &'a dyn Topology,
+  pub roots: &'a [TilePos],
+  pub power: &'a mut Power,
+}
+
+impl<'a> PowerGrid<'a> {
+  /// Clears the board and powers every network from its server.
+  pub fn reset(&mut self) {
+    *self.power = Power {
+      servers: Packed::for_max(self.cells.len(), self.roots.len() as u64),
+      touching: vec![Vec::new(); self.roots.len()],
+      ..Power::default()
+    };
+
+    for i in 0..self.cells.len() {
+      let channels = self.cells.channels(i);
+      self.power.unpowered += channels.iter().filter(|&&c| c != 0).count();
+      if self.cells.kind(i) == TileKind::TERMINAL {
+        self.power.unpowered_terminals += 1;
+      }
+    }
+
+    let servers: Vec<usize> = (0..self.roots.len()).collect();
+    self.walk_servers(&servers);
+
+    // Changes are only kept from here on, as the first walk changes all.
+    self.power.is_changed = vec![0; (2 * self.cells.len() + 63) / 64];
+  }
+
+  /// Updates the power after tile `i` turned from pipes `old` to its
+  /// current ones.
+  pub fn turned(&mut self, i: usize, old: Flag) {
+    // Bridges look the same every way round, so a tile that turns has a
+    // single channel.
+    let new = self.cells.directions(i);
+    let node = 2 * i;
+
+    if let Some(server) = self.server(node) {
+      if !self.power.touching[server].is_empty() {
+        return self.rewalk(i, old, server);
+      }
+
+      // Let go of whatever the pipes that turned away cut off from the
+      // server first.
+      let mut seeds = vec![node];
+      seeds.extend_from_slice(self.links(i, old & !new).as_slice());
+      if seeds.len() > 1 {
+        self.cut(server, seeds);
+      }
+    }
+
+    // Then join whatever the new pipes reach.
+    for &other in self.links(i, new & !old).as_slice() {
+      let a = self.server(node);
+      let b = self.server(other);
+      match (a, b) {
+        (Some(a), None) => self.walk(other, a),
+        (None, Some(b)) => self.walk(node, b),
+        (Some(a), Some(b)) if a != b => self.touch(a, b),
+        _ => {}
+      }
+    }
+
+    // Networks that just ran into each other are walked again, as the tiles
+    // in between go to whichever is walked first.
+    if let Some(server) = self.server(node) {
+      if !self.power.touching[server].is_empty() {
+        self.rewalk(i, old, server);
+      }
+    }
+  }
+
+  fn server(&self, node: usize) -> Option<usize> {
+    self.power.server(node)
+  }
+
+  /// Channels linked to tile `i` through its pipes in `pipes`.
+  fn links(&self, i: usize, pipes: Flag) -> Links {
+    let pos = self.topology.pos(i);
+    let mut links = Links::new();
+    for dir in self.topology.directions().iter() {
+      if (pipes & dir.flag) == 0 {
+        continue;
+      }
+
+      if let Some(n) = self.topology.neighbour(&pos, dir) {
+        let n = self.topology.index(&n);
+        if (self.cells.directions(n) & dir.opposite) != 0 {
+          links.push(2 * n + self.cells.channel(n, dir.opposite));
+        }
+      }
+    }
+    links
+  }
+
+  /// Channels linked to `node`, or merely next to it when `pipes_only` is
+  /// false.
+  fn neighbours(&self, node: usize, pipes_only: bool) -> Links {
+    let i = node / 2;
+    if pipes_only {
+      return self.links(i, self.cells.channels(i)[node % 2]);
+    }
+
+    let pos = self.topology.pos(i);
+    let mut nodes = Links::new();
+    for dir in self.topology.directions().iter() {
+      if self.cells.channel(i, dir.flag) != node % 2 {
+        continue;
+      }
+
+      if let Some(n) = self.topology.neighbour(&pos, dir) {
+        let n = self.topology.index(&n);
+        nodes.push(2 * n + self.cells.channel(n, dir.opposite));
+      }
+    }
+    nodes
+  }
+
+  /// Whether terminal `i` would be powered by a server of another colour.
+  fn is_wrong_color(&self, i: usize, server: Option<usize>) -> bool {
+    match (server, self.cells.color(i)) {
+      (Some(s), Some(color)) => {
+        let root = self.topology.index(&self.roots[s]);
+        color != self.cells.color(root).unwrap_or(0)
+      }
+      _ => false,
+    }
+  }
+
+  /// Powers channel `node` from `server`, keeping the totals.
+  fn set_server(&mut self, node: usize, server: Option<usize>) {
+    let i = node / 2;
+    let ch = node % 2;
+    let old = self.server(node);
+    if server == old {
+      return;
+    }
+    if let Some(word) = self.power.is_changed.get_mut(node / 64) {
+      if (*word & (1 << (node % 64))) == 0 {
+        *word |= 1 << (node % 64);
+        self.power.changed.push((node, old));
+      }
+    }
+
+    if self.cells.channels(i)[ch] != 0 {
+      self.power.unpowered += server.is_none() as usize;
+      self.power.unpowered -= old.is_none() as usize;
+    }
+    if ch == 0 && self.cells.kind(i) == TileKind::TERMINAL {
+      self.power.unpowered_terminals += server.is_none() as usize;
+      self.power.unpowered_terminals -= old.is_none() as usize;
+      let wrong = self.is_wrong_color(i, server) as usize;
+      self.power.wrong_colors += wrong;
+      self.power.wrong_colors -= self.is_wrong_color(i, old) as usize;
+    }
+
+    if ch == 0 {
+      self
+        .power
+        .servers
+        .set(i, server.map_or(0, |s| s as u64 + 1));
+    } else {
+      match server {
+        Some(s) => self.power.crossings.insert(i, s),
+        None => self.power.crossings.remove(&i),
+      };
+    }
+  }
+
+  fn touch(&mut self, a: usize, b: usize) {
+    if !self.power.touching[a].contains(&b) {
+      self.power.touching[a].push(b);
+      self.power.touching[b].push(a);
+    }
+  }
+
+  /// Powers everything linked to `start` from `server`.
+  fn walk(&mut self, start: usize, server: usize) {
+    self.set_server(start, Some(server));
+    let mut stack = vec![start];
+    while let Some(node) = stack.pop() {
+      for &other in self.neighbours(node, true).as_slice() {
+        match self.server(other) {
+          None => {
+            self.set_server(other, Some(server));
+            stack.push(other);
+          }
+          Some(s) if s != server => self.touch(server, s),
+          _ => {}
+        }
+      }
+    }
+  }
+
+  /// Walks the networks of `servers` from scratch. Every server is marked
+  /// first so a network reaching another server counts as touching it
+  /// even if that server hasn't been walked yet.
+  fn walk_servers(&mut self, servers: &[usize]) {
+    for &s in servers {
+      let node = 2 * self.topology.index(&self.roots[s]);
+      self.set_server(node, Some(s));
+    }
+    for &s in servers {
+      self.walk(2 * self.topology.index(&self.roots[s]), s);
+    }
+  }
+
+  /// Splits the network of `server` after some of its links were removed,
+  /// given a channel on each side of every removed link. Each piece is
+  /// searched side by side with the others, and the pieces cut off from
+  /// the server lose their power. As long as the server's own piece hasn't
+  /// been told apart the search stops with the last piece left, so a move
+  /// costs about as much as the pieces it cuts off.
+  fn cut(&mut self, server: usize, seeds: Vec<usize>) {
+    let root = 2 * self.topology.index(&self.roots[server]);
+    let mut pieces = Pieces {
+      owner: (0..seeds.len()).collect(),
+      stacks: vec![Vec::new(); seeds.len()],
+      nodes: vec![Vec::new(); seeds.len()],
+      rooted: vec![false; seeds.len()],
+      seen: HashMap::new(),
+      root: root,
+    };
+    for (j, &node) in seeds.iter().enumerate() {
+      pieces.reach(node, j);
+    }
+
+    loop {
+      let active: Vec<usize> = (0..seeds.len()).filter(|&j| pieces.is_active(j)).collect();
+      if active.is_empty() {
+        break;
+      }
+      if active.len() == 1 && !pieces.rooted.contains(&true) {
+        pieces.rooted[active[0]] = true;
+        break;
+      }
+
+      for j in active {
+        if !pieces.is_active(j) {
+          continue;
+        }
+
+        if let Some(node) = pieces.stacks[j].pop() {
+          for &other in self.neighbours(node, true).as_slice() {
+            if self.server(other) == Some(server) {
+              pieces.reach(other, j);
+            }
+          }
+        }
+      }
+    }
+
+    for j in 0..seeds.len() {
+      if pieces.owner[j] == j && !pieces.rooted[j] {
+        for node in std::mem::replace(&mut pieces.nodes[j], Vec::new()) {
+          self.set_server(node, None);
+        }
+      }
+    }
+  }
+
+  /// Walks again every network that touched tile `i` or is joined to one
+  /// that did, for moves on networks already running into each other,
+  /// where which of them claims the tiles in between depends on the order
+  /// they're walked in.
+  fn rewalk(&mut self, i: usize, old: Flag, server: usize) {
+    let mut servers = vec![server];
+    let new = self.cells.directions(i);
+    for &other in self.links(i, new).as_slice() {
+      if let Some(s) = self.server(other) {
+        if !servers.contains(&s) {
+          servers.push(s);
+        }
+      }
+    }
+    let mut k = 0;
+    while k < servers.len() {
+      for s in self.power.touching[servers[k]].clone() {
+        if !servers.contains(&s) {
+          servers.push(s);
+        }
+      }
+      k += 1;
+    }
+    servers.sort();
+
+    // The networks were connected before the move, so searching next
+    // door from the servers and the tile's old neighbours reaches all of
+    // them.
+    let mut stack: Vec<usize> = self.links(i, old).as_slice().to_vec();
+    stack.push(2 * i);
+    stack.push(2 * i + 1);
+    for &s in &servers {
+      stack.push(2 * self.topology.index(&self.roots[s]));
+    }
+
+    while let Some(node) = stack.pop() {
+      match self.server(node) {
+        Some(s) if servers.contains(&s) => {}
+        _ => continue,
+      }
+
+      self.set_server(node, None);
+      stack.extend_from_slice(self.neighbours(node, false).as_slice());
+    }
+
+    for &s in &servers {
+      self.power.touching[s].clear();
+    }
+    self.walk_servers(&servers);
+  }
+}
@@ -0,0 +1,114 @@
+use super::board::BoardOptions;
+use super::rng::GameSeed;
+use super::util::Size;
+use std::string::ToString;
+
+/// Everything needed to rebuild a puzzle exactly, written as
+/// `<width>x<height>-<seed>` followed by `-<options>` when the board options
+/// aren't the defaults.
+pub struct PuzzleId {
+  pub size: Size<i32>,
+  pub seed: GameSeed,
+  pub options: BoardOptions,
+}
+
+#[derive(Debug)]
+pub struct InvalidPuzzleIdError;
+
+impl PuzzleId {
+  pub fn from_str(s: &str) -> Result<Self, InvalidPuzzleIdError> {
+    let parts: Vec<&str> = s.splitn(3, '-').collect();
+    if parts.len() < 2 {
+      return Err(InvalidPuzzleIdError);
+    }
+
+    let size: Vec<&str> = parts[0].splitn(2, 'x').collect();
+    if size.len() != 2 {
+      return Err(InvalidPuzzleIdError);
+    }
+    let width = size[0].parse().map_err(|_| InvalidPuzzleIdError)?;
+    let height = size[1].parse().map_err(|_| InvalidPuzzleIdError)?;
+
+    let seed = GameSeed::from_str(parts[1]).map_err(|_| InvalidPuzzleIdError)?;
+
+    let options = match parts.get(2) {
+      Some(options) => {
+        let json = hex::decode(options).map_err(|_| InvalidPuzzleIdError)?;
+        serde_json::from_slice(&json).map_err(|_| InvalidPuzzleIdError)?
+      }
+      None => BoardOptions::default(),
+    };
+
+    Ok(Self {
+      size: Size::new(width, height),
+      seed: seed,
+      options: options,
+    })
+  }
+}
+
+impl ToString for PuzzleId {
+  fn to_string(&self) -> String {
+    let mut s = format!(
+      "{}x{}-{}",
+      self.size.width,
+      self.size.height,
+      self.seed.to_string()
+    );
+
+    if self.options != BoardOptions::default() {
+      let json = serde_json::to_string(&self.options).unwrap();
+      s.push('-');
+      s.push_str(&hex::encode(json));
+    }
+
+    s
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::board::Hints;
+  use super::super::direction::Grid;
+  use super::super::generator::{GeneratorConfig, RootPlacement};
+  use super::super::mask::MaskSource;
+  use super::*;
+
+  #[test]
+  fn round_trip() {
+    let options = [
+      BoardOptions::default(),
+      BoardOptions {
+        wrap: true,
+        grid: Grid::Hex,
+        mask: Some(MaskSource::Named("ring".to_string())),
+        walls: 0.25,
+        servers: 3,
+        colors: 2,
+        hints: Some(Hints::Percent(10.0)),
+        generator: GeneratorConfig {
+          terminals: Some(0.4),
+          root: RootPlacement::Center,
+          ..GeneratorConfig::default()
+        },
+        ..BoardOptions::default()
+      },
+    ];
+
+    for (n, options) in options.iter().enumerate() {
+      let id = PuzzleId {
+        size: Size::new(12, 9),
+        seed: GameSeed::from_arr([n as u8 + 1; 32]),
+        options: options.clone(),
+      };
+      let s = id.to_string();
+      assert_eq!(s.matches('-').count(), if n == 0 { 1 } else { 2 });
+
+      let read = PuzzleId::from_str(&s).unwrap();
+      assert_eq!(read.size, id.size);
+      assert_eq!(read.seed.to_string(), id.seed.to_string());
+      assert_eq!(&read.options, options);
+      assert_eq!(read.to_string(), s);
+    }
+  }
+}
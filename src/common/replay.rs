@@ -0,0 +1,132 @@
+use super::tile::{Rotation, TilePos};
+use serde::{Deserialize, Serialize};
+use std::string::ToString;
+
+/// Version of the format `Replay::to_string` writes. Version 1 only had
+/// right and left turns, logged as `direction`, and still reads as this
+/// one. Replays of any other version are refused rather than misread.
+pub const REPLAY_VERSION: u32 = 2;
+
+/// How a rotation came about.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum MoveKind {
+  Turn,
+  Undo,
+  Redo,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct LoggedMove {
+  pub kind: MoveKind,
+  /// The tile turned and how. For an undo that's the way back.
+  pub pos: TilePos,
+  #[serde(alias = "direction")]
+  pub rotation: Rotation,
+  /// Milliseconds since the game started.
+  pub time: u64,
+}
+
+/// A played game: the puzzle and every rotation made on it, in order.
+/// Written as a JSON object, see `Game::from_replay` for playing it back.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Replay {
+  pub version: u32,
+  /// The puzzle's id, see `PuzzleId`.
+  pub puzzle: String,
+  pub moves: Vec<LoggedMove>,
+}
+
+#[derive(Debug)]
+pub struct InvalidReplayError;
+
+impl Replay {
+  pub fn from_str(s: &str) -> Result<Self, InvalidReplayError> {
+    // The version is read on its own first, as other versions may not
+    // parse as this one at all.
+    #[derive(Deserialize)]
+    struct Versioned {
+      version: u32,
+    }
+
+    let versioned: Versioned = serde_json::from_str(s).map_err(|_| InvalidReplayError)?;
+    if versioned.version < 1 || versioned.version > REPLAY_VERSION {
+      return Err(InvalidReplayError);
+    }
+
+    serde_json::from_str(s).map_err(|_| InvalidReplayError)
+  }
+}
+
+impl ToString for Replay {
+  fn to_string(&self) -> String {
+    serde_json::to_string(self).unwrap()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::board::BoardOptions;
+  use super::super::game::Game;
+  use super::super::rng::GameSeed;
+  use super::*;
+
+  /// A game with turns both ways, undos and redos.
+  fn played() -> Game {
+    let options = BoardOptions {
+      wrap: true,
+      ..BoardOptions::default()
+    };
+    let mut game = Game::with_options(9, 9, GameSeed::from_arr([3; 32]), &options).unwrap();
+    for i in 0..40 {
+      let rotation = if i % 3 == 0 {
+        Rotation::Left
+      } else {
+        Rotation::Right
+      };
+      game.rotate_tile(&TilePos::new(i % 9, (i * 7) % 9), rotation);
+      if i % 5 == 0 {
+        game.undo();
+      }
+      if i % 10 == 0 {
+        game.redo();
+      }
+    }
+    game
+  }
+
+  #[test]
+  fn round_trip() {
+    let game = played();
+    let s = game.replay().to_string();
+    let replay = Replay::from_str(&s).unwrap();
+    assert_eq!(replay, game.replay());
+
+    let replayed = Game::from_replay(&replay).unwrap();
+    assert_eq!(replayed.board.get_directions(), game.board.get_directions());
+  }
+
+  #[test]
+  fn reads_version_one() {
+    let game = played();
+    let s = game
+      .replay()
+      .to_string()
+      .replace("\"version\":2", "\"version\":1")
+      .replace("\"rotation\"", "\"direction\"");
+    assert!(s.contains("\"direction\""));
+    let replay = Replay::from_str(&s).unwrap();
+    assert_eq!(replay.version, 1);
+
+    let replayed = Game::from_replay(&replay).unwrap();
+    assert_eq!(replayed.board.get_directions(), game.board.get_directions());
+  }
+
+  #[test]
+  fn refuses_unknown_versions() {
+    let s = played().replay().to_string();
+    for version in ["0", "3"].iter() {
+      let s = s.replace("\"version\":2", &format!("\"version\":{}", version));
+      assert!(Replay::from_str(&s).is_err());
+    }
+  }
+}
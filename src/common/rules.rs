@@ -0,0 +1,44 @@
+use super::direction::DirectionKind;
+use super::tile::TilePos;
+use serde::{Deserialize, Serialize};
+
+/// What it takes for a board to count as solved. Under every rule the
+/// networks of different servers must stay apart and terminals must be
+/// powered by a server of their own colour.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum WinRule {
+  /// Every terminal is powered.
+  Terminals,
+  /// Every tile with pipes is powered, including both channels of a bridge.
+  Powered,
+  /// Every tile is powered, no pipe ends open and no network has a loop.
+  /// With the pipes the generator lays, powering every tile already leaves
+  /// no room for loops or open ends, so this wins exactly when `Powered`
+  /// does, but reports loops and open ends before unpowered tiles.
+  Classic,
+}
+
+impl Default for WinRule {
+  fn default() -> Self {
+    Self::Terminals
+  }
+}
+
+/// The first thing that keeps a board from being solved.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum Violation {
+  /// Two servers' networks are connected.
+  ServersJoined,
+  /// A tile the rule needs powered isn't.
+  Unpowered { pos: TilePos },
+  /// A terminal is powered by a server of another colour.
+  WrongColor { pos: TilePos },
+  /// A pipe runs off the board, into a wall or into a tile without a pipe
+  /// to meet it.
+  OpenEnd {
+    pos: TilePos,
+    direction: DirectionKind,
+  },
+  /// The pipes close a loop through the tile.
+  Loop { pos: TilePos },
+}
@@ -0,0 +1,782 @@
+use super::board::Board;
+use super::direction::{Direction, DirectionKind};
+use super::tile::{Lock, RotationDirection, TileKind, TilePos};
+use super::util::{find, Flag};
+use serde::Serialize;
+use std::fmt;
+
+/// Finds orientations for a scrambled board.
+///
+/// Every tile starts with all of its distinct rotations as candidates. Edges
+/// between tiles are deduced to be linked or blank from the candidates on
+/// both sides, candidates that would close a loop, join two servers or two
+/// colours or seal off a network without a server are discarded, and when
+/// deduction stalls the solver guesses on the tile with the fewest candidates
+/// left and backtracks on contradiction.
+///
+/// Networks are tracked per channel rather than per tile, so the two pipes
+/// of a bridge can belong to different networks.
+pub struct Solver {
+  directions: &'static [Direction],
+  opposite: Vec<usize>,
+  orientations: Vec<Vec<Flag>>,
+  neighbours: Vec<Option<usize>>,
+  /// Network node of each tile's pipe in each direction. A tile is a single
+  /// node except for bridges, whose second channel gets a node of its own.
+  nodes: Vec<usize>,
+  is_bridge: Vec<bool>,
+  is_server: Vec<bool>,
+  colors: Vec<Option<u8>>,
+  servers: usize,
+  spanned: usize,
+  positions: Vec<TilePos>,
+}
+
+/// What it took to solve a board, for rating how hard it is.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SolveStats {
+  /// Tiles with more than one orientation to choose from.
+  pub tiles: usize,
+  /// Of those, tiles fixed by looking at their direct neighbours alone.
+  pub local: usize,
+  /// Tiles fixed by deduction with every rule, before the first guess.
+  pub deduced: usize,
+  /// Guesses made before reaching the first solution, dead ends included.
+  pub guesses: usize,
+  /// Average number of orientations over the tiles that can turn.
+  pub orientations: f32,
+}
+
+/// Why an orientation of a tile was ruled out.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum Reason {
+  /// It has a pipe running off the board or into a wall.
+  Border { direction: DirectionKind },
+  /// It has a pipe leading into a cell without pipes.
+  Empty { direction: DirectionKind },
+  /// The edge towards `direction` is already known to have a pipe across
+  /// it, or known not to, and the orientation disagrees.
+  Edge {
+    direction: DirectionKind,
+    linked: bool,
+  },
+  /// It would close a loop.
+  Loop,
+  /// It would connect two servers.
+  Servers,
+  /// It would join networks of different colours.
+  Colors,
+  /// It would close off a network that can't be completed.
+  Sealed,
+}
+
+/// A single step of deduction: an orientation ruled out for a tile.
+#[derive(Serialize, Debug, Clone)]
+pub struct Deduction {
+  pub pos: TilePos,
+  /// The orientation ruled out, as the directions of its pipes.
+  pub ruled_out: Flag,
+  pub reason: Reason,
+  /// Orientations still possible afterwards. A single one means the tile is
+  /// solved.
+  pub remaining: Vec<Flag>,
+}
+
+/// Every deduction made on a board before the solver would have to guess.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Explanation {
+  pub steps: Vec<Deduction>,
+  /// Whether deduction alone solves the board.
+  pub complete: bool,
+}
+
+#[derive(Debug)]
+struct Contradiction;
+
+#[derive(Clone)]
+struct State {
+  candidates: Vec<u8>,
+  known: Vec<Flag>,
+  links: Vec<Flag>,
+  parent: Vec<usize>,
+  size: Vec<u32>,
+  open: Vec<u32>,
+  servers: Vec<u32>,
+  colors: Vec<Option<u8>>,
+  /// Nodes and servers in networks that can't grow any more.
+  sealed: usize,
+  sealed_servers: usize,
+  queue: Vec<usize>,
+  /// Only rule out candidates that don't match the neighbouring edges,
+  /// leaving loops, servers and sealed networks alone.
+  local: bool,
+  /// Deductions made so far, when they're being recorded.
+  trace: Option<Vec<Deduction>>,
+}
+
+impl Solver {
+  pub fn new(board: &Board) -> Self {
+    let topology = board.get_topology();
+    let directions = topology.directions();
+    let cells = topology.len();
+
+    let opposite = directions
+      .iter()
+      .map(|d| {
+        directions
+          .iter()
+          .position(|o| o.flag == d.opposite)
+          .unwrap()
+      })
+      .collect();
+
+    let mut orientations = Vec::with_capacity(cells);
+    let mut positions = Vec::with_capacity(cells);
+    let mut neighbours = Vec::with_capacity(cells * directions.len());
+    let mut nodes = Vec::with_capacity(cells * directions.len());
+    let mut is_bridge = vec![false; cells];
+    let mut extra = cells;
+
+    for i in 0..cells {
+      let pos = topology.pos(i);
+      let tile = board.get_tile(&pos);
+      let mut flag = tile.directions;
+
+      // Hints can't turn, so their current orientation is the only one. The
+      // player's own locks may be wrong and don't count.
+      let turns = if tile.lock == Some(Lock::Hint) {
+        1
+      } else {
+        directions.len()
+      };
+      let mut rotations: Vec<Flag> = Vec::new();
+      for _ in 0..turns {
+        if !rotations.contains(&flag) {
+          rotations.push(flag);
+        }
+        flag = topology.rotate(flag, RotationDirection::Right, 1);
+      }
+      orientations.push(rotations);
+      positions.push(pos);
+
+      for dir in directions.iter() {
+        neighbours.push(topology.neighbour(&pos, dir).map(|p| topology.index(&p)));
+        nodes.push(if tile.channel(dir.flag) == 0 {
+          i
+        } else {
+          extra
+        });
+      }
+
+      // Bridges don't change under rotation, so their channels stay put.
+      if tile.kind == TileKind::BRIDGE {
+        is_bridge[i] = true;
+        extra += 1;
+      }
+    }
+
+    let spanned = orientations.iter().filter(|o| o[0] != 0).count() + (extra - cells);
+
+    let mut is_server = vec![false; extra];
+    for pos in board.get_roots() {
+      let i = topology.index(pos);
+      is_server[i] = orientations[i][0] != 0;
+    }
+    let servers = is_server.iter().filter(|&&s| s).count();
+    let mut colors = board.get_colors();
+    colors.resize(extra, None);
+
+    Self {
+      directions: directions,
+      opposite: opposite,
+      orientations: orientations,
+      neighbours: neighbours,
+      nodes: nodes,
+      is_bridge: is_bridge,
+      is_server: is_server,
+      colors: colors,
+      servers: servers,
+      spanned: spanned,
+      positions: positions,
+    }
+  }
+
+  /// Returns the solved directions of every tile, indexed like
+  /// `Board::get_directions`, or `None` if the board has no solution.
+  pub fn solve(&self) -> Option<Vec<Flag>> {
+    let mut solution = None;
+    self.search(&mut |st| {
+      solution = Some(st.links.clone());
+      false
+    });
+
+    solution
+  }
+
+  /// Counts the solutions of the board, stopping once `limit` are found.
+  pub fn count_solutions(&self, limit: usize) -> usize {
+    let mut n = 0;
+    self.search(&mut |_| {
+      n += 1;
+      n < limit
+    });
+
+    n
+  }
+
+  /// Solves the board once, keeping track of how much of it deduction
+  /// alone gets through and how many guesses the rest takes.
+  pub fn stats(&self) -> SolveStats {
+    let mut stats = SolveStats::default();
+    let turning: Vec<usize> = (0..self.cells())
+      .filter(|&cell| self.orientations[cell].len() > 1)
+      .collect();
+    stats.tiles = turning.len();
+    if !turning.is_empty() {
+      let total: usize = turning.iter().map(|&c| self.orientations[c].len()).sum();
+      stats.orientations = total as f32 / turning.len() as f32;
+    }
+
+    let fixed = |st: &State| {
+      turning
+        .iter()
+        .filter(|&&cell| st.candidates[cell].count_ones() == 1)
+        .count()
+    };
+
+    let mut st = self.initial_state();
+    st.local = true;
+    if self.propagate(&mut st).is_err() {
+      return stats;
+    }
+    stats.local = fixed(&st);
+
+    // Carrying on from the local deductions reaches the same fixpoint as
+    // starting over with every rule.
+    st.local = false;
+    st.queue = (0..self.cells()).rev().collect();
+    if self.propagate(&mut st).is_err() {
+      return stats;
+    }
+    stats.deduced = fixed(&st);
+
+    self.branch(st, &mut stats.guesses, &mut |_| false);
+
+    stats
+  }
+
+  /// Records every orientation that deduction rules out, and why, up to the
+  /// point where the solver would have to start guessing.
+  pub fn explain(&self) -> Explanation {
+    let mut st = self.initial_state();
+    st.trace = Some(Vec::new());
+    let consistent = self.propagate(&mut st).is_ok();
+
+    Explanation {
+      complete: consistent && st.candidates.iter().all(|c| c.count_ones() == 1),
+      steps: st.trace.unwrap_or_default(),
+    }
+  }
+
+  /// The orientation `cell` has in every solution, or `None` if solutions
+  /// disagree on it or there are none. Tries each of the cell's remaining
+  /// candidates in turn, so it's much slower than deduction.
+  pub fn forced(&self, cell: usize) -> Option<Flag> {
+    let mut st = self.initial_state();
+    self.propagate(&mut st).ok()?;
+
+    let mut forced = None;
+    for i in 0..self.orientations[cell].len() {
+      if (st.candidates[cell] & (1 << i)) == 0 {
+        continue;
+      }
+
+      let mut next = st.clone();
+      next.candidates[cell] = 1 << i;
+      next.queue.push(cell);
+      if self.propagate(&mut next).is_err() {
+        continue;
+      }
+
+      let mut solvable = false;
+      self.branch(next, &mut 0, &mut |_| {
+        solvable = true;
+        false
+      });
+      if solvable {
+        if forced.is_some() {
+          return None;
+        }
+        forced = Some(self.orientations[cell][i]);
+      }
+    }
+
+    forced
+  }
+
+  fn cells(&self) -> usize {
+    self.orientations.len()
+  }
+
+  fn neighbour(&self, cell: usize, d: usize) -> Option<usize> {
+    self.neighbours[cell * self.directions.len() + d]
+  }
+
+  fn node(&self, cell: usize, d: usize) -> usize {
+    self.nodes[cell * self.directions.len() + d]
+  }
+
+  fn initial_state(&self) -> State {
+    let cells = self.cells();
+    let nodes = self.is_server.len();
+    let mut st = State {
+      candidates: Vec::with_capacity(cells),
+      known: vec![0; cells],
+      links: vec![0; cells],
+      parent: (0..nodes).collect(),
+      size: vec![1; nodes],
+      open: vec![0; nodes],
+      servers: self.is_server.iter().map(|&s| s as u32).collect(),
+      colors: self.colors.clone(),
+      sealed: 0,
+      sealed_servers: 0,
+      queue: (0..cells).rev().collect(),
+      local: false,
+      trace: None,
+    };
+
+    for cell in 0..cells {
+      st.candidates
+        .push(((1u16 << self.orientations[cell].len()) - 1) as u8);
+
+      // Cells the generator never reached have no pipes and can't be
+      // linked to, so their edges are blank from the start.
+      for (d, dir) in self.directions.iter().enumerate() {
+        match self.neighbour(cell, d) {
+          Some(n) if !self.is_empty(cell) && !self.is_empty(n) => st.open[self.node(cell, d)] += 1,
+          _ => st.known[cell] |= dir.flag,
+        }
+      }
+    }
+
+    st
+  }
+
+  fn search<F>(&self, on_solution: &mut F) -> bool
+  where
+    F: FnMut(&State) -> bool,
+  {
+    let mut st = self.initial_state();
+    if self.propagate(&mut st).is_err() {
+      return true;
+    }
+
+    self.branch(st, &mut 0, on_solution)
+  }
+
+  /// Returns `false` once `on_solution` asks to stop searching. Every
+  /// candidate tried on an undecided tile counts towards `guesses`.
+  fn branch<F>(&self, st: State, guesses: &mut usize, on_solution: &mut F) -> bool
+  where
+    F: FnMut(&State) -> bool,
+  {
+    let mut best: Option<(usize, u32)> = None;
+    for (cell, c) in st.candidates.iter().enumerate() {
+      let n = c.count_ones();
+      if n > 1 && best.map_or(true, |(_, b)| n < b) {
+        best = Some((cell, n));
+      }
+    }
+
+    let cell = match best {
+      Some((cell, _)) => cell,
+      None => return on_solution(&st),
+    };
+
+    let candidates = st.candidates[cell];
+    for i in 0..self.orientations[cell].len() {
+      if (candidates & (1 << i)) == 0 {
+        continue;
+      }
+
+      let mut next = st.clone();
+      next.candidates[cell] = 1 << i;
+      next.queue.push(cell);
+      *guesses += 1;
+
+      if self.propagate(&mut next).is_ok() && !self.branch(next, guesses, on_solution) {
+        return false;
+      }
+    }
+
+    true
+  }
+
+  fn propagate(&self, st: &mut State) -> Result<(), Contradiction> {
+    while let Some(cell) = st.queue.pop() {
+      self.examine(st, cell)?;
+    }
+
+    Ok(())
+  }
+
+  fn examine(&self, st: &mut State, cell: usize) -> Result<(), Contradiction> {
+    let known = st.known[cell];
+    let links = st.links[cell];
+
+    let mut candidates = 0;
+    let mut must: Flag = !0;
+    let mut may: Flag = 0;
+    let mut ruled_out = Vec::new();
+
+    for (i, &o) in self.orientations[cell].iter().enumerate() {
+      if (st.candidates[cell] & (1 << i)) == 0 {
+        continue;
+      }
+
+      let fits = match self.edge_conflict(cell, o, known, links) {
+        Some(reason) => Err(reason),
+        None => self.fits(st, cell, o),
+      };
+      if let Err(reason) = fits {
+        if st.trace.is_some() {
+          ruled_out.push((o, reason));
+        }
+        continue;
+      }
+
+      candidates |= 1 << i;
+      must &= o;
+      may |= o;
+    }
+
+    if candidates == 0 {
+      return Err(Contradiction);
+    }
+    st.candidates[cell] = candidates;
+
+    if let Some(trace) = st.trace.as_mut() {
+      // Each step leaves what's left after it, so walk them backwards
+      // putting the orientations back one at a time.
+      let mut remaining: Vec<Flag> = (0..self.orientations[cell].len())
+        .filter(|&i| (candidates & (1 << i)) != 0)
+        .map(|i| self.orientations[cell][i])
+        .collect();
+      let mut steps = Vec::with_capacity(ruled_out.len());
+      for (o, reason) in ruled_out.into_iter().rev() {
+        steps.push(Deduction {
+          pos: self.positions[cell],
+          ruled_out: o,
+          reason: reason,
+          remaining: remaining.clone(),
+        });
+        remaining.push(o);
+      }
+      trace.extend(steps.into_iter().rev());
+    }
+
+    for (d, dir) in self.directions.iter().enumerate() {
+      if (st.known[cell] & dir.flag) != 0 {
+        continue;
+      }
+
+      if (must & dir.flag) != 0 {
+        self.set_edge(st, cell, d, true)?;
+      } else if (may & dir.flag) == 0 {
+        self.set_edge(st, cell, d, false)?;
+      }
+    }
+
+    Ok(())
+  }
+
+  /// The first edge of `cell` already decided differently from what
+  /// orientation `o` needs.
+  fn edge_conflict(&self, cell: usize, o: Flag, known: Flag, links: Flag) -> Option<Reason> {
+    let wrong = (o ^ links) & known;
+    if wrong == 0 {
+      return None;
+    }
+
+    let d = self.directions.iter().position(|d| (wrong & d.flag) != 0)?;
+    let dir = &self.directions[d];
+    let direction = dir.kind;
+    Some(match self.neighbour(cell, d) {
+      None => Reason::Border { direction },
+      Some(n) if self.is_empty(n) => Reason::Empty { direction },
+      Some(_) => Reason::Edge {
+        direction: direction,
+        linked: (links & dir.flag) != 0,
+      },
+    })
+  }
+
+  /// Checks that linking `cell` as `o` neither closes a loop, joins two
+  /// servers nor seals off a network that can't be completed.
+  fn fits(&self, st: &mut State, cell: usize, o: Flag) -> Result<(), Reason> {
+    // A bridge has a single orientation, so there is nothing to rule out.
+    let unknown = !st.known[cell] & self.all_flags();
+    if unknown == 0 || self.is_bridge[cell] || st.local {
+      return Ok(());
+    }
+
+    let own = find(&mut st.parent, cell);
+    let mut roots = vec![own];
+    let mut open = st.open[own];
+    let mut size = st.size[own];
+    let mut servers = st.servers[own];
+    let mut color = st.colors[own];
+
+    for (d, dir) in self.directions.iter().enumerate() {
+      if (unknown & o & dir.flag) == 0 {
+        continue;
+      }
+
+      let n = self.neighbour(cell, d).unwrap();
+      let root = find(&mut st.parent, self.node(n, self.opposite[d]));
+      if roots.contains(&root) {
+        return Err(Reason::Loop);
+      }
+      roots.push(root);
+      open += st.open[root];
+      size += st.size[root];
+      servers += st.servers[root];
+      color = match mix(color, st.colors[root]) {
+        Ok(c) => c,
+        Err(_) => return Err(Reason::Colors),
+      };
+    }
+
+    if servers > 1 {
+      return Err(Reason::Servers);
+    }
+
+    for (d, dir) in self.directions.iter().enumerate() {
+      if (unknown & dir.flag) == 0 {
+        continue;
+      }
+
+      if let Some(n) = self.neighbour(cell, d) {
+        let root = find(&mut st.parent, self.node(n, self.opposite[d]));
+        open -= if roots.contains(&root) { 2 } else { 1 };
+      }
+    }
+
+    if open > 0 || self.can_seal(st, size as usize, servers) {
+      Ok(())
+    } else {
+      Err(Reason::Sealed)
+    }
+  }
+
+  /// Checks that a network of `size` cells with `servers` servers may stop
+  /// growing: it needs its own server, and once every server's network is
+  /// sealed they must cover the whole board.
+  fn can_seal(&self, st: &State, size: usize, servers: u32) -> bool {
+    servers == 1 && (st.sealed_servers + 1 < self.servers || st.sealed + size == self.spanned)
+  }
+
+  fn set_edge(
+    &self,
+    st: &mut State,
+    cell: usize,
+    d: usize,
+    link: bool,
+  ) -> Result<(), Contradiction> {
+    let dir = &self.directions[d];
+    let n = self.neighbour(cell, d).unwrap();
+    let opposite = self.directions[self.opposite[d]].flag;
+
+    st.known[cell] |= dir.flag;
+    st.known[n] |= opposite;
+    if link {
+      st.links[cell] |= dir.flag;
+      st.links[n] |= opposite;
+    }
+
+    let a = find(&mut st.parent, self.node(cell, d));
+    let b = find(&mut st.parent, self.node(n, self.opposite[d]));
+    st.open[a] -= 1;
+    st.open[b] -= 1;
+
+    let mut roots = vec![a, b];
+    if link {
+      if a == b || st.servers[a] + st.servers[b] > 1 {
+        return Err(Contradiction);
+      }
+      st.colors[a] = mix(st.colors[a], st.colors[b])?;
+
+      st.parent[b] = a;
+      st.size[a] += st.size[b];
+      st.open[a] += st.open[b];
+      st.servers[a] += st.servers[b];
+      roots = vec![a];
+    } else if a == b {
+      roots = vec![a];
+    }
+
+    for root in roots {
+      if st.open[root] == 0 {
+        let size = st.size[root] as usize;
+        if !self.can_seal(st, size, st.servers[root]) {
+          return Err(Contradiction);
+        }
+
+        st.sealed += size;
+        st.sealed_servers += 1;
+      }
+    }
+
+    st.queue.push(cell);
+    st.queue.push(n);
+
+    Ok(())
+  }
+
+  fn is_empty(&self, cell: usize) -> bool {
+    self.orientations[cell][0] == 0
+  }
+
+  fn all_flags(&self) -> Flag {
+    self.directions.iter().fold(0, |acc, d| acc | d.flag)
+  }
+}
+
+impl fmt::Display for Reason {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Reason::Border { direction } => write!(
+        f,
+        "its {} pipe would run off the board or into a wall",
+        direction_name(*direction)
+      ),
+      Reason::Empty { direction } => write!(
+        f,
+        "its {} pipe would lead into an empty cell",
+        direction_name(*direction)
+      ),
+      Reason::Edge {
+        direction,
+        linked: true,
+      } => write!(
+        f,
+        "its {} side is known to need a pipe",
+        direction_name(*direction)
+      ),
+      Reason::Edge {
+        direction,
+        linked: false,
+      } => write!(
+        f,
+        "its {} side is known to have no pipe",
+        direction_name(*direction)
+      ),
+      Reason::Loop => write!(f, "it would close a loop"),
+      Reason::Servers => write!(f, "it would connect two servers"),
+      Reason::Colors => write!(f, "it would join networks of different colours"),
+      Reason::Sealed => write!(f, "it would close off a network that can't be completed"),
+    }
+  }
+}
+
+impl fmt::Display for Deduction {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "tile ({}, {}) can't be turned that way: {}",
+      self.pos.x, self.pos.y, self.reason
+    )?;
+    match self.remaining.len() {
+      1 => write!(f, ", so it only fits one way"),
+      n => write!(f, ", which leaves {} ways to turn it", n),
+    }
+  }
+}
+
+fn direction_name(kind: DirectionKind) -> String {
+  format!("{:?}", kind).to_lowercase().replace('_', "-")
+}
+
+/// Colour of a network made by joining networks of colours `a` and `b`.
+fn mix(a: Option<u8>, b: Option<u8>) -> Result<Option<u8>, Contradiction> {
+  match (a, b) {
+    (Some(a), Some(b)) if a != b => Err(Contradiction),
+    _ => Ok(a.or(b)),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::board::BoardOptions;
+  use super::super::direction::Grid;
+  use super::super::rng::{GameRng, GameSeed};
+  use super::super::tile::Rotation;
+  use super::*;
+
+  fn boards(options: &BoardOptions) -> Vec<Board> {
+    (0..8)
+      .map(|n| {
+        let mut rng = GameRng::from(GameSeed::from_arr([n; 32]));
+        let mut board = Board::with_options(7, 6, options, &mut rng).unwrap();
+        board.scramble(&mut rng);
+        board
+      })
+      .collect()
+  }
+
+  /// Turning every tile the way `solve` has it wins the board.
+  #[test]
+  fn solution_solves() {
+    let options = [
+      BoardOptions::default(),
+      BoardOptions {
+        wrap: true,
+        ..BoardOptions::default()
+      },
+      BoardOptions {
+        grid: Grid::Hex,
+        ..BoardOptions::default()
+      },
+      BoardOptions {
+        servers: 3,
+        colors: 2,
+        ..BoardOptions::default()
+      },
+      BoardOptions {
+        bridges: 0.5,
+        ..BoardOptions::default()
+      },
+      BoardOptions {
+        walls: 0.3,
+        ..BoardOptions::default()
+      },
+    ];
+
+    for options in options.iter() {
+      for mut board in boards(options) {
+        let solution = Solver::new(&board).solve().unwrap();
+        let width = board.get_size().width;
+        for (i, &directions) in solution.iter().enumerate() {
+          let pos = TilePos::new(i as i32 % width, i as i32 / width);
+          board.rotate_tile(&pos, Rotation::To(directions));
+        }
+        assert!(board.is_solved());
+      }
+    }
+  }
+
+  /// Boards generated to have one solution still have one once scrambled.
+  #[test]
+  fn unique_counts_one() {
+    for &grid in [Grid::Square, Grid::Hex].iter() {
+      let options = BoardOptions {
+        unique: true,
+        grid: grid,
+        ..BoardOptions::default()
+      };
+      for board in boards(&options) {
+        assert_eq!(Solver::new(&board).count_solutions(2), 1);
+      }
+    }
+  }
+}
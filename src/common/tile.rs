@@ -1,13 +1,26 @@
-use super::direction::DIRECTIONS;
+use super::direction::DirectionKind;
 use super::util::{BitFlag, Flag, Pos};
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
 pub enum RotationDirection {
   Right,
   Left,
 }
 
+/// A turn of a tile as a player asks for it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Rotation {
+  /// One step clockwise.
+  Right,
+  /// One step counter-clockwise.
+  Left,
+  /// Half way round, three steps on hex grids.
+  Half,
+  /// Straight to the given pipes, which have to be a turn of the tile's own.
+  To(Flag),
+}
+
 pub type TilePos = Pos<i32>;
 pub type TileOffset = Pos<i32>;
 
@@ -17,6 +30,13 @@ pub struct Tile {
   pub directions: Flag,
   pub neighbours: Flag,
   pub powered: bool,
+  /// Server powering each of the tile's channels, see `channels`.
+  pub servers: [Option<usize>; 2],
+  /// Colour of a server or terminal. A terminal only counts as powered by a
+  /// server of the same colour.
+  pub color: Option<u8>,
+  /// Locked tiles can't be rotated.
+  pub lock: Option<Lock>,
 }
 
 impl Tile {
@@ -26,22 +46,9 @@ impl Tile {
       directions: 0,
       neighbours: 0,
       powered: false,
-    }
-  }
-
-  pub fn rotate(&mut self, rd: RotationDirection, n: i32) {
-    for _ in 0..n {
-      let mut new = 0;
-      for dir in DIRECTIONS.iter() {
-        if (dir.flag & self.directions) > 0 {
-          new |= match rd {
-            RotationDirection::Left => dir.left,
-            RotationDirection::Right => dir.right,
-          };
-        }
-      }
-
-      self.directions = new;
+      servers: [None; 2],
+      color: None,
+      lock: None,
     }
   }
 
@@ -53,6 +60,22 @@ impl Tile {
     self.neighbours.count_bits()
   }
 
+  /// Splits the pipes into the channels power flows through. Only a bridge
+  /// has a second channel, its horizontal pipe, which crosses the vertical
+  /// one without joining it.
+  pub fn channels(&self) -> [Flag; 2] {
+    channels(self.kind, self.directions)
+  }
+
+  /// Index into `channels` of the channel the pipe `flag` belongs to.
+  pub fn channel(&self, flag: Flag) -> usize {
+    if (self.channels()[1] & flag) != 0 {
+      1
+    } else {
+      0
+    }
+  }
+
   // pub fn rand_free_dir(&self, rng: &mut GameRng) -> Option<Direction> {
   //   let n = self.free_directions() as usize;
   //   if n == 0 {
@@ -71,12 +94,33 @@ impl Tile {
   // }
 }
 
+/// Splits the pipes `directions` of a tile of `kind` into channels, see
+/// `Tile::channels`.
+pub fn channels(kind: TileKind, directions: Flag) -> [Flag; 2] {
+  if kind == TileKind::BRIDGE {
+    let vertical = DirectionKind::UP as Flag | DirectionKind::DOWN as Flag;
+    [directions & vertical, directions & !vertical]
+  } else {
+    [directions, 0]
+  }
+}
+
+/// Why a tile is locked.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Lock {
+  /// Given away solved when the game started, for good.
+  Hint,
+  /// Marked by the player, who can unlock it again.
+  Player,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum TileKind {
   UNDEFINED = 0,
   SERVER = 1,
   TERMINAL = 2,
   CONNECTOR = 3,
+  BRIDGE = 4,
 }
 
 impl Default for TileKind {
@@ -0,0 +1,501 @@
+use super::direction::{Direction, Grid, DIRECTIONS, HEX_DIRECTIONS};
+use super::mask::Mask;
+use super::rng::GameRng;
+use super::tile::{RotationDirection, TileOffset, TilePos};
+use super::util::{find, Flag, Size};
+use rand::Rng;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+/// The shape of a board: which cells exist, how they are indexed, which cell
+/// lies in each direction of another and how a tile's directions rotate.
+pub trait Topology: Debug + Send + Sync {
+  fn size(&self) -> Size<i32>;
+
+  fn grid(&self) -> Grid;
+
+  fn directions(&self) -> &'static [Direction];
+
+  /// Offset of the cell in direction `dir`, ignoring the board's edges.
+  fn offset(&self, pos: &TilePos, dir: &Direction) -> TileOffset;
+
+  fn contains(&self, pos: &TilePos) -> bool {
+    let size = self.size();
+    pos.x >= 0 && pos.y >= 0 && pos.x < size.width && pos.y < size.height
+  }
+
+  fn neighbour(&self, pos: &TilePos, dir: &Direction) -> Option<TilePos> {
+    let pos = *pos + self.offset(pos, dir);
+    if self.contains(&pos) {
+      Some(pos)
+    } else {
+      None
+    }
+  }
+
+  /// Index of the cell in direction `dir` of the cell at `index`, see
+  /// `neighbour`. Layers over a grid get this from the trait, so one call
+  /// through a `dyn Topology` covers every layer.
+  fn neighbour_index(&self, index: usize, dir: &Direction) -> Option<usize> {
+    self
+      .neighbour(&self.pos(index), dir)
+      .map(|pos| self.index(&pos))
+  }
+
+  fn is_wrapping(&self) -> bool {
+    false
+  }
+
+  /// Directions of `pos` that are blocked by a wall.
+  fn walls(&self, _: &TilePos) -> Flag {
+    0
+  }
+
+  fn len(&self) -> usize {
+    let size = self.size();
+    (size.width * size.height) as usize
+  }
+
+  fn index(&self, pos: &TilePos) -> usize {
+    (pos.x + pos.y * self.size().width) as usize
+  }
+
+  fn pos(&self, index: usize) -> TilePos {
+    let width = self.size().width;
+    let index = index as i32;
+    TilePos::new(index % width, index / width)
+  }
+
+  fn rotate(&self, flag: Flag, rd: RotationDirection, n: i32) -> Flag {
+    let mut flag = flag;
+    for _ in 0..n {
+      let mut new = 0;
+      for dir in self.directions().iter() {
+        if (dir.flag & flag) > 0 {
+          new |= match rd {
+            RotationDirection::Left => dir.left,
+            RotationDirection::Right => dir.right,
+          };
+        }
+      }
+
+      flag = new;
+    }
+
+    flag
+  }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SquareGrid {
+  size: Size<i32>,
+}
+
+impl SquareGrid {
+  pub fn new(width: i32, height: i32) -> Self {
+    Self {
+      size: Size::new(width, height),
+    }
+  }
+}
+
+impl Topology for SquareGrid {
+  fn size(&self) -> Size<i32> {
+    self.size
+  }
+
+  fn grid(&self) -> Grid {
+    Grid::Square
+  }
+
+  fn directions(&self) -> &'static [Direction] {
+    &DIRECTIONS
+  }
+
+  fn offset(&self, _: &TilePos, dir: &Direction) -> TileOffset {
+    dir.offset
+  }
+}
+
+/// Pointy-top hexagons laid out in rows, odd rows shifted half a tile right.
+#[derive(Debug, Clone, Copy)]
+pub struct HexGrid {
+  size: Size<i32>,
+}
+
+impl HexGrid {
+  pub fn new(width: i32, height: i32) -> Self {
+    Self {
+      size: Size::new(width, height),
+    }
+  }
+}
+
+impl Topology for HexGrid {
+  fn size(&self) -> Size<i32> {
+    self.size
+  }
+
+  fn grid(&self) -> Grid {
+    Grid::Hex
+  }
+
+  fn directions(&self) -> &'static [Direction] {
+    &HEX_DIRECTIONS
+  }
+
+  fn offset(&self, pos: &TilePos, dir: &Direction) -> TileOffset {
+    if (pos.y & 1) == 1 {
+      dir.odd_offset
+    } else {
+      dir.offset
+    }
+  }
+}
+
+/// Joins the right edge of a board to its left and the bottom to its top.
+#[derive(Debug, Clone, Copy)]
+pub struct Wrapping<T: Topology>(pub T);
+
+impl<T: Topology> Topology for Wrapping<T> {
+  fn size(&self) -> Size<i32> {
+    self.0.size()
+  }
+
+  fn grid(&self) -> Grid {
+    self.0.grid()
+  }
+
+  fn directions(&self) -> &'static [Direction] {
+    self.0.directions()
+  }
+
+  fn offset(&self, pos: &TilePos, dir: &Direction) -> TileOffset {
+    self.0.offset(pos, dir)
+  }
+
+  fn contains(&self, pos: &TilePos) -> bool {
+    self.0.contains(pos)
+  }
+
+  fn neighbour(&self, pos: &TilePos, dir: &Direction) -> Option<TilePos> {
+    let size = self.size();
+    let mut pos = *pos + self.offset(pos, dir);
+    pos.x = pos.x.rem_euclid(size.width);
+    pos.y = pos.y.rem_euclid(size.height);
+
+    if self.contains(&pos) {
+      Some(pos)
+    } else {
+      None
+    }
+  }
+
+  fn is_wrapping(&self) -> bool {
+    true
+  }
+
+  fn walls(&self, pos: &TilePos) -> Flag {
+    self.0.walls(pos)
+  }
+
+  fn len(&self) -> usize {
+    self.0.len()
+  }
+
+  fn index(&self, pos: &TilePos) -> usize {
+    self.0.index(pos)
+  }
+
+  fn pos(&self, index: usize) -> TilePos {
+    self.0.pos(index)
+  }
+
+  fn rotate(&self, flag: Flag, rd: RotationDirection, n: i32) -> Flag {
+    self.0.rotate(flag, rd, n)
+  }
+}
+
+/// Leaves out the cells a mask marks as holes.
+#[derive(Debug, Clone)]
+pub struct Masked<T: Topology> {
+  inner: T,
+  mask: Mask,
+}
+
+impl<T: Topology> Masked<T> {
+  pub fn new(inner: T, mask: Mask) -> Self {
+    Self {
+      inner: inner,
+      mask: mask,
+    }
+  }
+}
+
+impl<T: Topology> Topology for Masked<T> {
+  fn size(&self) -> Size<i32> {
+    self.inner.size()
+  }
+
+  fn grid(&self) -> Grid {
+    self.inner.grid()
+  }
+
+  fn directions(&self) -> &'static [Direction] {
+    self.inner.directions()
+  }
+
+  fn offset(&self, pos: &TilePos, dir: &Direction) -> TileOffset {
+    self.inner.offset(pos, dir)
+  }
+
+  fn contains(&self, pos: &TilePos) -> bool {
+    self.inner.contains(pos) && self.mask.contains(pos)
+  }
+
+  fn neighbour(&self, pos: &TilePos, dir: &Direction) -> Option<TilePos> {
+    self
+      .inner
+      .neighbour(pos, dir)
+      .filter(|pos| self.mask.contains(pos))
+  }
+
+  fn is_wrapping(&self) -> bool {
+    self.inner.is_wrapping()
+  }
+
+  fn walls(&self, pos: &TilePos) -> Flag {
+    self.inner.walls(pos)
+  }
+
+  fn len(&self) -> usize {
+    self.inner.len()
+  }
+
+  fn index(&self, pos: &TilePos) -> usize {
+    self.inner.index(pos)
+  }
+
+  fn pos(&self, index: usize) -> TilePos {
+    self.inner.pos(index)
+  }
+
+  fn rotate(&self, flag: Flag, rd: RotationDirection, n: i32) -> Flag {
+    self.inner.rotate(flag, rd, n)
+  }
+}
+
+/// Blocks the edges marked in `walls` as if the cells weren't adjacent.
+#[derive(Debug, Clone)]
+pub struct Walled<T: Topology> {
+  inner: T,
+  walls: Vec<Flag>,
+}
+
+impl<T: Topology> Walled<T> {
+  pub fn new(inner: T, walls: Vec<Flag>) -> Self {
+    Self {
+      inner: inner,
+      walls: walls,
+    }
+  }
+
+  /// Walls off roughly a `density` share of the edges, keeping a random
+  /// spanning tree of the cells open so the board stays connected.
+  pub fn random(inner: T, density: f32, rng: &mut GameRng) -> Self {
+    let directions = inner.directions();
+    let mut edges = Vec::new();
+    for i in 0..inner.len() {
+      let pos = inner.pos(i);
+      if !inner.contains(&pos) {
+        continue;
+      }
+
+      for (d, dir) in directions.iter().enumerate() {
+        if let Some(n) = inner.neighbour(&pos, dir) {
+          if inner.index(&n) > i {
+            edges.push((i, d, inner.index(&n)));
+          }
+        }
+      }
+    }
+
+    for i in (1..edges.len()).rev() {
+      edges.swap(i, rng.gen_range(0, i + 1));
+    }
+
+    let mut parent: Vec<usize> = (0..inner.len()).collect();
+    let mut walls = vec![0; inner.len()];
+    for (a, d, b) in edges {
+      let ra = find(&mut parent, a);
+      let rb = find(&mut parent, b);
+      if ra != rb {
+        parent[ra] = rb;
+      } else if rng.gen::<f32>() < density {
+        walls[a] |= directions[d].flag;
+        walls[b] |= directions[d].opposite;
+      }
+    }
+
+    Self::new(inner, walls)
+  }
+}
+
+impl<T: Topology> Topology for Walled<T> {
+  fn size(&self) -> Size<i32> {
+    self.inner.size()
+  }
+
+  fn grid(&self) -> Grid {
+    self.inner.grid()
+  }
+
+  fn directions(&self) -> &'static [Direction] {
+    self.inner.directions()
+  }
+
+  fn offset(&self, pos: &TilePos, dir: &Direction) -> TileOffset {
+    self.inner.offset(pos, dir)
+  }
+
+  fn contains(&self, pos: &TilePos) -> bool {
+    self.inner.contains(pos)
+  }
+
+  fn neighbour(&self, pos: &TilePos, dir: &Direction) -> Option<TilePos> {
+    if (self.walls(pos) & dir.flag) != 0 {
+      return None;
+    }
+
+    self.inner.neighbour(pos, dir)
+  }
+
+  fn is_wrapping(&self) -> bool {
+    self.inner.is_wrapping()
+  }
+
+  fn walls(&self, pos: &TilePos) -> Flag {
+    self.walls[self.inner.index(pos)] | self.inner.walls(pos)
+  }
+
+  fn len(&self) -> usize {
+    self.inner.len()
+  }
+
+  fn index(&self, pos: &TilePos) -> usize {
+    self.inner.index(pos)
+  }
+
+  fn pos(&self, index: usize) -> TilePos {
+    self.inner.pos(index)
+  }
+
+  fn rotate(&self, flag: Flag, rd: RotationDirection, n: i32) -> Flag {
+    self.inner.rotate(flag, rd, n)
+  }
+}
+
+impl<T: Topology + ?Sized> Topology for Arc<T> {
+  fn size(&self) -> Size<i32> {
+    self.as_ref().size()
+  }
+
+  fn grid(&self) -> Grid {
+    self.as_ref().grid()
+  }
+
+  fn directions(&self) -> &'static [Direction] {
+    self.as_ref().directions()
+  }
+
+  fn offset(&self, pos: &TilePos, dir: &Direction) -> TileOffset {
+    self.as_ref().offset(pos, dir)
+  }
+
+  fn contains(&self, pos: &TilePos) -> bool {
+    self.as_ref().contains(pos)
+  }
+
+  fn neighbour(&self, pos: &TilePos, dir: &Direction) -> Option<TilePos> {
+    self.as_ref().neighbour(pos, dir)
+  }
+
+  fn neighbour_index(&self, index: usize, dir: &Direction) -> Option<usize> {
+    self.as_ref().neighbour_index(index, dir)
+  }
+
+  fn is_wrapping(&self) -> bool {
+    self.as_ref().is_wrapping()
+  }
+
+  fn walls(&self, pos: &TilePos) -> Flag {
+    self.as_ref().walls(pos)
+  }
+
+  fn len(&self) -> usize {
+    self.as_ref().len()
+  }
+
+  fn index(&self, pos: &TilePos) -> usize {
+    self.as_ref().index(pos)
+  }
+
+  fn pos(&self, index: usize) -> TilePos {
+    self.as_ref().pos(index)
+  }
+
+  fn rotate(&self, flag: Flag, rd: RotationDirection, n: i32) -> Flag {
+    self.as_ref().rotate(flag, rd, n)
+  }
+}
+
+/// Checks that every cell of the topology can be reached from every other.
+pub fn is_connected(topology: &dyn Topology) -> bool {
+  let cells: Vec<TilePos> = (0..topology.len())
+    .map(|i| topology.pos(i))
+    .filter(|pos| topology.contains(pos))
+    .collect();
+
+  let start = match cells.first() {
+    Some(pos) => *pos,
+    None => return false,
+  };
+
+  let mut seen = vec![false; topology.len()];
+  let mut stack = vec![start];
+  let mut n = 0;
+  seen[topology.index(&start)] = true;
+
+  while let Some(pos) = stack.pop() {
+    n += 1;
+    for dir in topology.directions().iter() {
+      if let Some(next) = topology.neighbour(&pos, dir) {
+        let i = topology.index(&next);
+        if !seen[i] {
+          seen[i] = true;
+          stack.push(next);
+        }
+      }
+    }
+  }
+
+  n == cells.len()
+}
+
+/// Checks whether some cell reaches another one in two directions, as on a
+/// torus two cells wide. Every way of joining the two then has a mirror
+/// image, so no puzzle on the topology has a unique solution.
+pub fn has_double_edges(topology: &dyn Topology) -> bool {
+  (0..topology.len())
+    .map(|i| topology.pos(i))
+    .filter(|pos| topology.contains(pos))
+    .any(|pos| {
+      let neighbours: Vec<TilePos> = topology
+        .directions()
+        .iter()
+        .filter_map(|dir| topology.neighbour(&pos, dir))
+        .filter(|next| *next != pos)
+        .collect();
+      (1..neighbours.len()).any(|i| neighbours[..i].contains(&neighbours[i]))
+    })
+}
@@ -72,14 +72,15 @@ pub trait BitFlag {
 
 impl BitFlag for Flag {
   fn count_bits(&self) -> u8 {
-    let mut n = *self;
-    let mut c = 0;
-    while n != 0u8 {
-      if (n & 1) == 1 {
-        c += 1;
-      }
-      n = n >> 1;
-    }
-    c
+    self.count_ones() as u8
+  }
+}
+
+/// Root of `i` in a union-find forest, halving the path on the way.
+pub fn find(parent: &mut [usize], mut i: usize) -> usize {
+  while parent[i] != i {
+    parent[i] = parent[parent[i]];
+    i = parent[i];
   }
+  i
 }
@@ -0,0 +1,145 @@
+use super::game::Game;
+use super::replay::{MoveKind, Replay};
+use serde::Serialize;
+
+/// Turns in a row that have to take at least `MIN_BURST_MS` between them.
+/// Anything faster is a script rather than a player.
+pub const MOVE_BURST: usize = 20;
+pub const MIN_BURST_MS: u64 = 1000;
+
+/// Why a result wasn't accepted.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum Rejection {
+  /// The moves can't be played on the puzzle.
+  InvalidReplay,
+  /// The moves don't solve the puzzle.
+  Unsolved,
+  /// Moves out of order, or later than the game has been running.
+  BadTiming,
+  /// Moves made faster than a player could.
+  TooFast,
+}
+
+/// Checks that a finished game's moves were made at times a player could
+/// have made them, then plays them on the puzzle and checks they solve it.
+/// `elapsed` is how long the game has been running, in milliseconds.
+pub fn verify(replay: &Replay, elapsed: u64) -> Result<Game, Rejection> {
+  check_timing(replay, elapsed)?;
+
+  let game = Game::from_replay(replay).map_err(|_| Rejection::InvalidReplay)?;
+  if !game.board.is_solved() {
+    return Err(Rejection::Unsolved);
+  }
+
+  Ok(game)
+}
+
+fn check_timing(replay: &Replay, elapsed: u64) -> Result<(), Rejection> {
+  let mut last = 0;
+  for m in &replay.moves {
+    if m.time < last || m.time > elapsed {
+      return Err(Rejection::BadTiming);
+    }
+    last = m.time;
+  }
+
+  // Undos and redos don't help solving, and holding down their keys
+  // repeats them faster than anyone clicks.
+  let turns: Vec<u64> = replay
+    .moves
+    .iter()
+    .filter(|m| m.kind == MoveKind::Turn)
+    .map(|m| m.time)
+    .collect();
+  if turns
+    .windows(MOVE_BURST)
+    .any(|burst| burst[MOVE_BURST - 1] - burst[0] < MIN_BURST_MS)
+  {
+    return Err(Rejection::TooFast);
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::replay::LoggedMove;
+  use super::super::rng::GameSeed;
+  use super::super::solver::Solver;
+  use super::super::tile::{Rotation, TilePos};
+  use super::*;
+
+  /// Solves a game turn by turn, undoing and redoing each turn, and spaces
+  /// the turns `gap` milliseconds apart with the undo and redo right after.
+  fn solved(gap: u64) -> Replay {
+    let mut game = Game::new(7, 7, GameSeed::from_arr([5; 32]));
+    let solution = Solver::new(&game.board).solve().unwrap();
+    for (i, &directions) in solution.iter().enumerate() {
+      let pos = TilePos::new(i as i32 % 7, i as i32 / 7);
+      if game.rotate_tile(&pos, Rotation::To(directions)).is_some() {
+        game.undo();
+        game.redo();
+      }
+    }
+
+    let mut replay = game.replay();
+    let mut time = 0;
+    for m in replay.moves.iter_mut() {
+      time += if m.kind == MoveKind::Turn { gap } else { 1 };
+      m.time = time;
+    }
+    replay
+  }
+
+  fn last_time(replay: &Replay) -> u64 {
+    replay.moves.last().unwrap().time
+  }
+
+  #[test]
+  fn accepts_solved() {
+    let replay = solved(100);
+    assert!(replay.moves.len() > 3 * MOVE_BURST);
+    let game = verify(&replay, last_time(&replay)).unwrap();
+    assert!(game.board.is_solved());
+  }
+
+  #[test]
+  fn rejects_unsolved() {
+    let mut replay = solved(100);
+    replay.moves.clear();
+    assert_eq!(verify(&replay, 0).err(), Some(Rejection::Unsolved));
+  }
+
+  #[test]
+  fn rejects_bad_timing() {
+    let replay = solved(100);
+    let elapsed = last_time(&replay);
+    assert_eq!(
+      verify(&replay, elapsed - 1).err(),
+      Some(Rejection::BadTiming)
+    );
+
+    let mut swapped = replay.clone();
+    let (first, second) = (swapped.moves[0].time, swapped.moves[3].time);
+    swapped.moves[0].time = second;
+    swapped.moves[3].time = first;
+    assert_eq!(verify(&swapped, elapsed).err(), Some(Rejection::BadTiming));
+  }
+
+  #[test]
+  fn rejects_fast_turns() {
+    let replay = solved(MIN_BURST_MS / MOVE_BURST as u64 - 1);
+    let elapsed = last_time(&replay);
+    assert_eq!(verify(&replay, elapsed).err(), Some(Rejection::TooFast));
+
+    // The undos and redos between them make no difference, however fast.
+    let replay = solved(MIN_BURST_MS / (MOVE_BURST as u64 - 1));
+    let turns: Vec<&LoggedMove> = replay
+      .moves
+      .iter()
+      .filter(|m| m.kind == MoveKind::Turn)
+      .collect();
+    assert!(turns.len() >= MOVE_BURST);
+    assert!(verify(&replay, last_time(&replay)).is_ok());
+  }
+}
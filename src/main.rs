@@ -1,4 +1,5 @@
-mod common;
+use netwalk::common;
+
 mod server;
 
 // fn main() {
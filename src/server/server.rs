@@ -5,18 +5,28 @@ use actix_web_actors::ws;
 use std::collections::HashMap;
 
 use num_traits::FromPrimitive;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use super::session::Session;
+use crate::common::board::{BoardOptions, TileHint};
+use crate::common::difficulty::Difficulty;
+use crate::common::direction::Grid;
 use crate::common::game::Game as NetWalk;
+use crate::common::history::Move;
+use crate::common::puzzle::PuzzleId;
 use crate::common::rng::GameSeed;
-use crate::common::tile::{RotationDirection, TilePos};
+use crate::common::rules::{Violation, WinRule};
+use crate::common::tile::{Lock, Rotation, TilePos};
 use crate::common::util::{Flag, Pos, Size};
+use crate::common::verify;
 
 use num_derive::{FromPrimitive, ToPrimitive};
 
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
+/// Time added for every hint unless `HINT_PENALTY` sets it in seconds.
+const DEFAULT_HINT_PENALTY: Duration = Duration::from_secs(30);
+
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct Connect {
@@ -45,9 +55,65 @@ pub struct Disconnect {
 #[derive(Serialize, Deserialize)]
 pub struct NewGameResult {
   pub root: TilePos,
+  pub roots: Vec<TilePos>,
   pub seed: String,
+  /// Rebuilds this exact puzzle when passed back in `NewGame`.
+  pub puzzle: String,
   pub tiles: Vec<u8>,
   pub size: Size<i32>,
+  pub wrap: bool,
+  pub grid: Grid,
+  pub mask: Option<Vec<bool>>,
+  pub walls: Vec<Flag>,
+  pub colors: Vec<Option<u8>>,
+  pub bridges: Vec<TilePos>,
+  /// Tiles that start solved and can't be rotated.
+  pub locked: Vec<TilePos>,
+  /// Tiles the player has locked.
+  pub player_locked: Vec<TilePos>,
+  /// Index into `roots` of the server powering each channel of every tile.
+  /// Only bridges use the second channel. Moves only send the changes, see
+  /// `UpdateGameState`.
+  pub servers: Vec<[Option<usize>; 2]>,
+  /// Milliseconds since the game started.
+  pub time: u128,
+  /// How hard the puzzle is, from 0 to 100. Huge boards aren't rated.
+  pub difficulty: Option<f32>,
+  pub rule: WinRule,
+  /// Moves undone in this game so far.
+  pub undos: usize,
+  /// Fewest rotations that solve the puzzle.
+  pub par: usize,
+  pub rotations: usize,
+}
+
+impl NewGameResult {
+  /// `penalty` is added to the time the game has been running.
+  pub fn new(game: &NetWalk, penalty: Duration) -> Self {
+    Self {
+      root: game.board.get_root(),
+      roots: game.board.get_roots().to_vec(),
+      size: game.board.get_size(),
+      tiles: game.board.get_directions(),
+      seed: game.rng.seed().to_string(),
+      puzzle: game.puzzle_id().to_string(),
+      wrap: game.board.is_wrapping(),
+      grid: game.board.get_grid(),
+      mask: game.board.get_mask(),
+      walls: game.board.get_walls(),
+      colors: game.board.get_colors(),
+      bridges: game.board.get_bridges(),
+      locked: game.board.get_locked(Lock::Hint),
+      player_locked: game.board.get_locked(Lock::Player),
+      servers: game.board.get_servers(),
+      time: (game.board.get_start_time().elapsed() + penalty).as_millis(),
+      difficulty: game.difficulty(),
+      rule: game.options.win_rule(),
+      undos: game.undos(),
+      par: game.board.get_par(),
+      rotations: game.rotations(),
+    }
+  }
 }
 
 #[derive(Serialize, Deserialize, FromPrimitive, ToPrimitive)]
@@ -55,6 +121,12 @@ pub enum ClientMethodKind {
   SetToken = 0,
   SetGame,
   UpdateGameState,
+  UpdateTileLock,
+  ShowHint,
+  /// The current game's `Replay`.
+  SetReplay,
+  AcceptResult,
+  RejectResult,
 }
 
 #[derive(Serialize, Deserialize, FromPrimitive, ToPrimitive, Debug)]
@@ -62,6 +134,17 @@ pub enum ServerMethodKind {
   Login = 0,
   NewGame,
   RotateTile,
+  LockTile,
+  UnlockTile,
+  /// Sends the current game again, e.g. after the client lost its state.
+  Resync,
+  Hint,
+  /// Takes back the last rotation, see `Game::undo`.
+  Undo,
+  Redo,
+  GetReplay,
+  /// Asks the server to verify the finished game, see `AcceptedResult`.
+  SubmitResult,
 }
 
 pub type Method = i32;
@@ -184,14 +267,27 @@ impl ClientRequest {
 #[derive(Message, Serialize, Deserialize, Debug)]
 #[rtype(result = "Option<ClientRequest>")]
 pub struct NewGame {
-  pub size: Size<i32>,
+  /// Defaults to the difficulty's own size when a difficulty is given.
+  #[serde(default)]
+  pub size: Option<Size<i32>>,
   pub seed: Option<String>,
+  /// Keeps drawing seeds until the puzzle is this hard. Ignored when a
+  /// seed is given.
+  #[serde(default)]
+  pub difficulty: Option<Difficulty>,
+  #[serde(default)]
+  pub options: BoardOptions,
+  /// Replaces the size, seed and options when given.
+  #[serde(default)]
+  pub puzzle: Option<String>,
 }
 
 #[derive(Message, Serialize, Deserialize, Debug)]
 #[rtype(result = "Option<ClientRequest>")]
 pub struct RotateTile {
-  pub direction: RotationDirection,
+  /// Older clients only sent right turns, as `direction`.
+  #[serde(alias = "direction")]
+  pub rotation: Rotation,
   pub pos: Pos<i32>,
 }
 
@@ -200,55 +296,316 @@ pub struct RotateTile {
 pub struct UpdateGameState {
   pub pos: Pos<i32>,
   pub flag: Flag,
+  /// The rotation left the tile looking as it was, like any turn of a
+  /// cross. It isn't counted as a move.
+  pub no_op: bool,
   pub is_solved: bool,
   pub time: u128,
+  /// Channels whose server changed with this move, numbered
+  /// `2 * tile + channel`, with the index into the game's `roots` of the
+  /// server powering each now.
+  pub servers: Vec<(usize, Option<usize>)>,
+  /// What still breaks the game's win rule, `None` once solved. Only
+  /// reported under the classic rule.
+  pub violation: Option<Violation>,
+  /// Moves undone in this game so far, zero for a clean run.
+  pub undos: usize,
+  /// Tiles turned so far, see `Game::rotations`.
+  pub rotations: usize,
+}
+
+#[derive(Message, Serialize, Deserialize, Debug)]
+#[rtype(result = "Option<ClientRequest>")]
+pub struct LockTile {
+  pub pos: Pos<i32>,
+}
+
+#[derive(Message, Serialize, Deserialize, Debug)]
+#[rtype(result = "Option<ClientRequest>")]
+pub struct UnlockTile {
+  pub pos: Pos<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UpdateTileLock {
+  pub pos: Pos<i32>,
+  pub locked: bool,
+}
+
+#[derive(Message, Serialize, Deserialize, Debug)]
+#[rtype(result = "Option<ClientRequest>")]
+pub struct Resync {}
+
+#[derive(Message, Serialize, Deserialize, Debug)]
+#[rtype(result = "Option<ClientRequest>")]
+pub struct Hint {}
+
+#[derive(Message, Serialize, Deserialize, Debug)]
+#[rtype(result = "Option<ClientRequest>")]
+pub struct Undo {}
+
+#[derive(Message, Serialize, Deserialize, Debug)]
+#[rtype(result = "Option<ClientRequest>")]
+pub struct Redo {}
+
+#[derive(Message, Serialize, Deserialize, Debug)]
+#[rtype(result = "Option<ClientRequest>")]
+pub struct GetReplay {}
+
+#[derive(Message, Serialize, Deserialize, Debug)]
+#[rtype(result = "Option<ClientRequest>")]
+pub struct SubmitResult {}
+
+/// A solved game whose moves the server has played back and checked.
+#[derive(Serialize, Debug, Clone)]
+pub struct AcceptedResult {
+  /// Issued by the server, names this result from now on.
+  pub id: String,
+  pub puzzle: String,
+  /// Milliseconds until the solving move, hint penalties included.
+  pub time: u128,
+  pub hints: usize,
+  pub undos: usize,
+  pub rotations: usize,
+  pub par: usize,
+  /// From 1 to 3, see `Game::stars`.
+  pub stars: u8,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ShowHint {
+  pub hint: TileHint,
+  /// The hint's reasons written out, one sentence each.
+  pub explanation: Vec<String>,
+  /// Hints used in this game so far, this one included.
+  pub hints: usize,
+  /// Time since the game started, hint penalties included.
+  pub time: u128,
+}
+
+impl Server {
+  /// Lets `turn` turn a tile of the client's game and tells the client
+  /// how it ended up.
+  fn turn_tile<F>(&mut self, token: &str, turn: F) -> Option<ClientRequest>
+  where
+    F: FnOnce(&mut NetWalk) -> Option<Move>,
+  {
+    let t = Instant::now();
+
+    let client = self.sessions.get_mut(token)?;
+    let penalty = client.penalty(self.hint_penalty);
+    let game = client.game.as_mut()?;
+    let m = turn(game)?;
+
+    // Finding the violation means looking through the whole board, so only
+    // do it for the rule whose client shows it.
+    let violation = if game.options.rule == WinRule::Classic {
+      game.board.violation()
+    } else {
+      None
+    };
+
+    Some(ClientRequest::new(
+      ClientMethodKind::UpdateGameState,
+      &UpdateGameState {
+        pos: m.pos,
+        flag: m.to,
+        no_op: m.is_no_op(),
+        is_solved: game.board.is_solved(),
+        time: (t.duration_since(game.board.get_start_time()) + penalty).as_millis(),
+        servers: game.board.take_server_changes(),
+        violation: violation,
+        undos: game.undos(),
+        rotations: game.rotations(),
+      },
+    ))
+  }
+
+  fn set_lock(&mut self, token: &str, pos: Pos<i32>, locked: bool) -> Option<ClientRequest> {
+    let game = self.sessions.get_mut(token)?.game.as_mut()?;
+    if !game.board.set_lock(&pos, locked) {
+      return None;
+    }
+
+    Some(ClientRequest::new(
+      ClientMethodKind::UpdateTileLock,
+      &UpdateTileLock {
+        pos: pos,
+        locked: locked,
+      },
+    ))
+  }
+}
+
+impl Handler<ServerRequest<LockTile>> for Server {
+  type Result = Option<ClientRequest>;
+
+  fn handle(&mut self, req: ServerRequest<LockTile>, _: &mut Context<Self>) -> Self::Result {
+    self.set_lock(&req.token, req.data.pos, true)
+  }
+}
+
+impl Handler<ServerRequest<UnlockTile>> for Server {
+  type Result = Option<ClientRequest>;
+
+  fn handle(&mut self, req: ServerRequest<UnlockTile>, _: &mut Context<Self>) -> Self::Result {
+    self.set_lock(&req.token, req.data.pos, false)
+  }
+}
+
+impl Handler<ServerRequest<Resync>> for Server {
+  type Result = Option<ClientRequest>;
+
+  fn handle(&mut self, req: ServerRequest<Resync>, _: &mut Context<Self>) -> Self::Result {
+    let client = self.sessions.get(&req.token)?;
+    let game = client.game.as_ref()?;
+
+    Some(ClientRequest::new(
+      ClientMethodKind::SetGame,
+      &NewGameResult::new(game, client.penalty(self.hint_penalty)),
+    ))
+  }
+}
+
+impl Handler<ServerRequest<Hint>> for Server {
+  type Result = Option<ClientRequest>;
+
+  fn handle(&mut self, req: ServerRequest<Hint>, _: &mut Context<Self>) -> Self::Result {
+    let client = self.sessions.get_mut(&req.token)?;
+    let hint = client.game.as_ref()?.board.hint()?;
+    client.hints += 1;
+
+    let started = client.game.as_ref()?.board.get_start_time();
+    Some(ClientRequest::new(
+      ClientMethodKind::ShowHint,
+      &ShowHint {
+        explanation: hint.reasons.iter().map(|r| r.to_string()).collect(),
+        hint: hint,
+        hints: client.hints,
+        time: (started.elapsed() + client.penalty(self.hint_penalty)).as_millis(),
+      },
+    ))
+  }
 }
 
 impl Handler<ServerRequest<RotateTile>> for Server {
   type Result = Option<ClientRequest>;
 
   fn handle(&mut self, req: ServerRequest<RotateTile>, _: &mut Context<Self>) -> Self::Result {
-    let t = Instant::now();
+    let RotateTile { rotation, pos } = req.data;
+    self.turn_tile(&req.token, |game| game.rotate_tile(&pos, rotation))
+  }
+}
 
-    if let Some(client) = self.sessions.get_mut(&req.token) {
-      if let Some(game) = &mut client.game {
-        let r = game.board.rotate_tile(&req.data.pos, req.data.direction);
-        let b = game.board.is_solved();
-
-        if r > 0 {
-          return Some(ClientRequest::new(
-            ClientMethodKind::UpdateGameState,
-            &UpdateGameState {
-              pos: req.data.pos,
-              flag: r,
-              is_solved: b,
-              time: t.duration_since(game.board.get_start_time()).as_millis(),
-            },
-          ));
-        }
-      }
+impl Handler<ServerRequest<Undo>> for Server {
+  type Result = Option<ClientRequest>;
+
+  fn handle(&mut self, req: ServerRequest<Undo>, _: &mut Context<Self>) -> Self::Result {
+    self.turn_tile(&req.token, |game| game.undo())
+  }
+}
+
+impl Handler<ServerRequest<Redo>> for Server {
+  type Result = Option<ClientRequest>;
+
+  fn handle(&mut self, req: ServerRequest<Redo>, _: &mut Context<Self>) -> Self::Result {
+    self.turn_tile(&req.token, |game| game.redo())
+  }
+}
+
+impl Handler<ServerRequest<GetReplay>> for Server {
+  type Result = Option<ClientRequest>;
+
+  fn handle(&mut self, req: ServerRequest<GetReplay>, _: &mut Context<Self>) -> Self::Result {
+    let game = self.sessions.get(&req.token)?.game.as_ref()?;
+
+    Some(ClientRequest::new(
+      ClientMethodKind::SetReplay,
+      &game.replay(),
+    ))
+  }
+}
+
+impl Handler<ServerRequest<SubmitResult>> for Server {
+  type Result = Option<ClientRequest>;
+
+  fn handle(&mut self, req: ServerRequest<SubmitResult>, _: &mut Context<Self>) -> Self::Result {
+    let client = self.sessions.get_mut(&req.token)?;
+    if let Some(result) = &client.result {
+      return Some(ClientRequest::new(ClientMethodKind::AcceptResult, result));
     }
 
-    None
+    // The server's own move log is played back rather than trusting the
+    // board it was played on.
+    let game = client.game.as_ref()?;
+    let replay = game.replay();
+    let elapsed = game.board.get_start_time().elapsed().as_millis() as u64;
+    let verified = match verify::verify(&replay, elapsed) {
+      Ok(game) => game,
+      Err(rejection) => {
+        return Some(ClientRequest::new(
+          ClientMethodKind::RejectResult,
+          &rejection,
+        ));
+      }
+    };
+
+    let solved_at = Duration::from_millis(replay.moves.last().map_or(0, |m| m.time));
+    let result = AcceptedResult {
+      id: nanoid::generate(16),
+      puzzle: replay.puzzle,
+      time: (solved_at + client.penalty(self.hint_penalty)).as_millis(),
+      hints: client.hints,
+      undos: verified.undos(),
+      rotations: verified.rotations(),
+      par: verified.board.get_par(),
+      stars: verified.stars(),
+    };
+
+    client.result = Some(result.clone());
+    self.results.insert(result.id.clone(), result.clone());
+    Some(ClientRequest::new(ClientMethodKind::AcceptResult, &result))
   }
 }
 
 pub struct Client {
   pub game: Option<NetWalk>,
+  /// Hints used in the current game.
+  pub hints: usize,
+  /// The current game's result, once accepted.
+  pub result: Option<AcceptedResult>,
+}
+
+impl Client {
+  /// Time added to the game for the hints used.
+  pub fn penalty(&self, per_hint: Duration) -> Duration {
+    per_hint * self.hints as u32
+  }
 }
 
 pub struct Server {
   sessions: HashMap<String, Client>,
+  hint_penalty: Duration,
+  /// Every accepted result, by id.
+  results: HashMap<String, AcceptedResult>,
 }
 
-impl Default for Server {
-  fn default() -> Server {
+impl Server {
+  pub fn new(hint_penalty: Duration) -> Server {
     Server {
       sessions: HashMap::new(),
+      hint_penalty: hint_penalty,
+      results: HashMap::new(),
     }
   }
 }
 
+impl Default for Server {
+  fn default() -> Server {
+    Server::new(DEFAULT_HINT_PENALTY)
+  }
+}
+
 impl Actor for Server {
   type Context = Context<Self>;
 }
@@ -288,27 +645,46 @@ impl Handler<ServerRequest<NewGame>> for Server {
 
   fn handle(&mut self, req: ServerRequest<NewGame>, _: &mut Context<Self>) -> Self::Result {
     if let Some(session) = self.sessions.get_mut(&req.token) {
-      let seed = if let Some(seed) = req.data.seed {
-        match GameSeed::from_str(&seed) {
-          Ok(seed) => seed,
+      let game = if let Some(puzzle) = req.data.puzzle {
+        match PuzzleId::from_str(&puzzle) {
+          Ok(id) => NetWalk::from_puzzle_id(&id),
           Err(_) => return None,
         }
       } else {
-        GameSeed::new()
+        let size = match (req.data.size, req.data.difficulty) {
+          (Some(size), _) => size,
+          (None, Some(difficulty)) => {
+            let (width, height) = difficulty.default_size();
+            Size::new(width, height)
+          }
+          (None, None) => return None,
+        };
+
+        match (req.data.seed, req.data.difficulty) {
+          (Some(seed), _) => match GameSeed::from_str(&seed) {
+            Ok(seed) => NetWalk::with_options(size.height, size.width, seed, &req.data.options),
+            Err(_) => return None,
+          },
+          (None, Some(difficulty)) => {
+            NetWalk::with_difficulty(size.height, size.width, difficulty, &req.data.options)
+          }
+          (None, None) => {
+            NetWalk::with_options(size.height, size.width, GameSeed::new(), &req.data.options)
+          }
+        }
       };
 
-      let mut game = NetWalk::new(req.data.size.height, req.data.size.width, seed);
-
-      let res = NewGameResult {
-        root: game.board.get_root(),
-        size: game.board.get_size(),
-        tiles: game.board.get_directions(),
-        seed: game.rng.seed().to_string(),
+      let mut game = match game {
+        Ok(game) => game,
+        Err(_) => return None,
       };
 
       game.board.start_timer();
+      let res = NewGameResult::new(&game, Duration::from_secs(0));
 
       session.game = Some(game);
+      session.hints = 0;
+      session.result = None;
 
       return Some(ClientRequest::new(ClientMethodKind::SetGame, &res));
     }
@@ -336,7 +712,11 @@ async fn web_socket_index(
 pub async fn start_server() -> std::io::Result<()> {
   env_logger::init();
 
-  let server = Server::default().start();
+  let hint_penalty = std::env::var("HINT_PENALTY")
+    .ok()
+    .and_then(|s| s.parse().ok())
+    .map_or(DEFAULT_HINT_PENALTY, Duration::from_secs);
+  let server = Server::new(hint_penalty).start();
 
   HttpServer::new(move || {
     App::new()
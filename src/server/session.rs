@@ -60,7 +60,11 @@ impl Actor for Session {
 
     self.addr.do_send(server::Connect {
       id: self.id.clone(),
-      session: server::Client { game: None },
+      session: server::Client {
+        game: None,
+        hints: 0,
+        result: None,
+      },
     });
   }
 }
@@ -112,6 +116,78 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for Session {
 
             self.send(req, ctx);
           }
+          ServerMethodKind::LockTile => {
+            let req: ServerRequest<server::LockTile> =
+              match ServerRequest::new(self.id.clone(), req.data) {
+                Some(req) => req,
+                None => return,
+              };
+
+            self.send(req, ctx);
+          }
+          ServerMethodKind::UnlockTile => {
+            let req: ServerRequest<server::UnlockTile> =
+              match ServerRequest::new(self.id.clone(), req.data) {
+                Some(req) => req,
+                None => return,
+              };
+
+            self.send(req, ctx);
+          }
+          ServerMethodKind::Resync => {
+            let req: ServerRequest<server::Resync> =
+              match ServerRequest::new(self.id.clone(), req.data) {
+                Some(req) => req,
+                None => return,
+              };
+
+            self.send(req, ctx);
+          }
+          ServerMethodKind::Hint => {
+            let req: ServerRequest<server::Hint> =
+              match ServerRequest::new(self.id.clone(), req.data) {
+                Some(req) => req,
+                None => return,
+              };
+
+            self.send(req, ctx);
+          }
+          ServerMethodKind::Undo => {
+            let req: ServerRequest<server::Undo> =
+              match ServerRequest::new(self.id.clone(), req.data) {
+                Some(req) => req,
+                None => return,
+              };
+
+            self.send(req, ctx);
+          }
+          ServerMethodKind::Redo => {
+            let req: ServerRequest<server::Redo> =
+              match ServerRequest::new(self.id.clone(), req.data) {
+                Some(req) => req,
+                None => return,
+              };
+
+            self.send(req, ctx);
+          }
+          ServerMethodKind::GetReplay => {
+            let req: ServerRequest<server::GetReplay> =
+              match ServerRequest::new(self.id.clone(), req.data) {
+                Some(req) => req,
+                None => return,
+              };
+
+            self.send(req, ctx);
+          }
+          ServerMethodKind::SubmitResult => {
+            let req: ServerRequest<server::SubmitResult> =
+              match ServerRequest::new(self.id.clone(), req.data) {
+                Some(req) => req,
+                None => return,
+              };
+
+            self.send(req, ctx);
+          }
           //self.send(
           //   server::NewGame {
           //     id: self.id.clone(),
@@ -33,6 +33,10 @@ interface HighScore {
   seed: string;
   time: number;
   created_at: number;
+  // missing from scores saved before undo existed
+  undos?: number;
+  // id the server gave the result, missing from scores saved before that
+  result?: string;
 }
 
 interface Save {
@@ -55,7 +59,13 @@ function load(key: string) {
   return null;
 }
 
-function save(size: Size, seed: string, time: number) {
+function save(
+  size: Size,
+  seed: string,
+  time: number,
+  undos: number,
+  result: string
+) {
   let key = size.width.toString() + "x" + size.height.toString();
   var save = load(key);
   var last_high_score = null;
@@ -68,13 +78,21 @@ function save(size: Size, seed: string, time: number) {
       save.score.created_at = Date.now();
       save.score.seed = seed;
       save.score.time = time;
+      save.score.undos = undos;
+      save.score.result = result;
       is_high_score = true;
     }
   } else {
     is_high_score = true;
     save = {
       times_played: 1,
-      score: { created_at: Date.now(), seed: seed, time: time }
+      score: {
+        created_at: Date.now(),
+        seed: seed,
+        time: time,
+        undos: undos,
+        result: result
+      }
     };
   }
 
@@ -92,20 +110,46 @@ function save(size: Size, seed: string, time: number) {
 enum TileKind {
   CONNECTOR,
   SERVER,
-  TERMINAL
+  TERMINAL,
+  BRIDGE
 }
 
 interface Tile {
   kind: TileKind;
   flag: number;
   is_powered: boolean;
+  // horizontal pipe of a bridge
+  is_cross_powered: boolean;
+  servers: (number | null)[];
+  color: number | null;
+  is_locked: boolean;
+  is_player_locked: boolean;
+}
+
+const BRIDGE_VERTICAL = 1 | 4;
+
+// pipes of each channel power flows through, only bridges have a second one
+function tile_channels(tile: Tile) {
+  if (tile.kind == TileKind.BRIDGE) {
+    return [tile.flag & BRIDGE_VERTICAL, tile.flag & ~BRIDGE_VERTICAL];
+  }
+
+  return [tile.flag, 0];
+}
+
+function tile_channel(tile: Tile, flag: number) {
+  return tile_channels(tile)[1] & flag ? 1 : 0;
 }
 
 enum DirectionKind {
   UP = 1,
   RIGHT = 2,
   DOWN = 4,
-  LEFT = 8
+  LEFT = 8,
+  UP_RIGHT,
+  DOWN_RIGHT,
+  DOWN_LEFT,
+  UP_LEFT
 }
 
 interface Direction {
@@ -113,6 +157,12 @@ interface Direction {
   flag: number;
   opposite: number;
   offset: Pos;
+  odd_offset: Pos;
+}
+
+enum Grid {
+  Square = "Square",
+  Hex = "Hex"
 }
 
 const DIRECTIONS: Direction[] = [
@@ -120,28 +170,95 @@ const DIRECTIONS: Direction[] = [
     kind: DirectionKind.UP,
     flag: 1,
     opposite: 4,
-    offset: { x: 0, y: -1 }
+    offset: { x: 0, y: -1 },
+    odd_offset: { x: 0, y: -1 }
   },
   {
     kind: DirectionKind.RIGHT,
     flag: 2,
     opposite: 8,
-    offset: { x: 1, y: 0 }
+    offset: { x: 1, y: 0 },
+    odd_offset: { x: 1, y: 0 }
   },
   {
     kind: DirectionKind.DOWN,
     flag: 4,
     opposite: 1,
-    offset: { x: 0, y: 1 }
+    offset: { x: 0, y: 1 },
+    odd_offset: { x: 0, y: 1 }
   },
   {
     kind: DirectionKind.LEFT,
     flag: 8,
     opposite: 2,
-    offset: { x: -1, y: 0 }
+    offset: { x: -1, y: 0 },
+    odd_offset: { x: -1, y: 0 }
   }
 ];
 
+// pointy-top hexagons in clockwise order, odd rows are shifted half a tile right
+const HEX_DIRECTIONS: Direction[] = [
+  {
+    kind: DirectionKind.UP_RIGHT,
+    flag: 1,
+    opposite: 8,
+    offset: { x: 0, y: -1 },
+    odd_offset: { x: 1, y: -1 }
+  },
+  {
+    kind: DirectionKind.RIGHT,
+    flag: 2,
+    opposite: 16,
+    offset: { x: 1, y: 0 },
+    odd_offset: { x: 1, y: 0 }
+  },
+  {
+    kind: DirectionKind.DOWN_RIGHT,
+    flag: 4,
+    opposite: 32,
+    offset: { x: 0, y: 1 },
+    odd_offset: { x: 1, y: 1 }
+  },
+  {
+    kind: DirectionKind.DOWN_LEFT,
+    flag: 8,
+    opposite: 1,
+    offset: { x: -1, y: 1 },
+    odd_offset: { x: 0, y: 1 }
+  },
+  {
+    kind: DirectionKind.LEFT,
+    flag: 16,
+    opposite: 2,
+    offset: { x: -1, y: 0 },
+    odd_offset: { x: -1, y: 0 }
+  },
+  {
+    kind: DirectionKind.UP_LEFT,
+    flag: 32,
+    opposite: 4,
+    offset: { x: -1, y: -1 },
+    odd_offset: { x: 0, y: -1 }
+  }
+];
+
+// angle of each hex direction in degrees, clockwise from the x axis
+const HEX_ANGLES = [-60, 0, 60, 120, 180, 240];
+
+// distance between hex rows and from a hex center to its corner, in tile widths
+const HEX_ROW = Math.sqrt(3) / 2;
+const HEX_RADIUS = 1 / Math.sqrt(3);
+
+// server colours, the first one is used when there is only one
+const COLORS = [
+  "#7d32a8",
+  "#2f6fd6",
+  "#d68a2f",
+  "#2fb5b5",
+  "#c93a9c",
+  "#8a8a2f"
+];
+
 function $(v: string) {
   return document.getElementById(v);
 }
@@ -170,13 +287,26 @@ function split_n(s: string, pattern: string, n: number) {
 enum ClientMethodKind {
   SetToken = 0,
   SetGame,
-  UpdateGameState
+  UpdateGameState,
+  UpdateTileLock,
+  ShowHint,
+  SetReplay,
+  AcceptResult,
+  RejectResult
 }
 
 enum ServerMethodKind {
   Login = 0,
   NewGame,
-  RotateTile
+  RotateTile,
+  LockTile,
+  UnlockTile,
+  Resync,
+  Hint,
+  Undo,
+  Redo,
+  GetReplay,
+  SubmitResult
 }
 
 interface Pos {
@@ -194,21 +324,158 @@ enum RotationDirection {
   Left = "Left"
 }
 
+// a half turn, or straight to the given pipes
+type Rotation = RotationDirection | "Half" | { To: number };
+
 interface RotateTile {
   pos: Pos;
-  direction: RotationDirection;
+  rotation: Rotation;
+}
+
+interface TileLock {
+  pos: Pos;
+}
+
+interface UpdateTileLock {
+  pos: Pos;
+  locked: boolean;
+}
+
+interface TileHint {
+  pos: Pos;
+  directions: number;
+}
+
+interface ShowHint {
+  hint: TileHint;
+  explanation: string[];
+  hints: number;
+  time: number;
 }
 
 interface UpdateGameState {
   pos: Pos;
   flag: number;
+  no_op: boolean;
   is_solved: boolean;
   time: number;
+  // channels whose server changed, numbered 2 * tile + channel
+  servers: [number, number | null][];
+  violation: Violation | null;
+  undos: number;
+  rotations: number;
+}
+
+interface LoggedMove {
+  kind: "Turn" | "Undo" | "Redo";
+  pos: Pos;
+  rotation: Rotation;
+  time: number;
+}
+
+interface Replay {
+  version: number;
+  puzzle: string;
+  moves: LoggedMove[];
+}
+
+interface AcceptedResult {
+  id: string;
+  puzzle: string;
+  time: number;
+  hints: number;
+  undos: number;
+  rotations: number;
+  par: number;
+  stars: number;
+}
+
+type Rejection = "InvalidReplay" | "Unsolved" | "BadTiming" | "TooFast";
+
+enum WinRule {
+  Terminals = "Terminals",
+  Powered = "Powered",
+  Classic = "Classic"
+}
+
+type Violation =
+  | "ServersJoined"
+  | { Unpowered: { pos: Pos } }
+  | { WrongColor: { pos: Pos } }
+  | { OpenEnd: { pos: Pos; direction: string } }
+  | { Loop: { pos: Pos } };
+
+function describe_violation(v: Violation) {
+  if (v == "ServersJoined") {
+    return "two servers are connected";
+  }
+  const at = (pos: Pos) => " at (" + pos.x + ", " + pos.y + ")";
+  if ("Unpowered" in v) {
+    return "unpowered tile" + at(v.Unpowered.pos);
+  }
+  if ("WrongColor" in v) {
+    return "terminal of the wrong colour" + at(v.WrongColor.pos);
+  }
+  if ("OpenEnd" in v) {
+    return (
+      "open " + v.OpenEnd.direction.toLowerCase() + " pipe" + at(v.OpenEnd.pos)
+    );
+  }
+  return "loop" + at(v.Loop.pos);
+}
+
+interface MaskSource {
+  Named?: string;
+  Ascii?: string;
+  // hex encoded PBM image
+  Pbm?: string;
+}
+
+interface HintAmount {
+  Count?: number;
+  Percent?: number;
 }
 
+enum RootPlacement {
+  Random = "Random",
+  Center = "Center",
+  Corner = "Corner"
+}
+
+enum Algorithm {
+  Prim = "Prim",
+  Wilson = "Wilson",
+  Backtracker = "Backtracker",
+  Kruskal = "Kruskal"
+}
+
+interface GeneratorConfig {
+  algorithm: Algorithm;
+  root: RootPlacement;
+}
+
+interface BoardOptions {
+  unique: boolean;
+  wrap: boolean;
+  grid: Grid;
+  mask: MaskSource | null;
+  walls: number;
+  servers: number;
+  colors: number;
+  bridges: number;
+  hints: HintAmount | null;
+  generator: GeneratorConfig;
+  rule: WinRule;
+}
+
+type Difficulty = "easy" | "medium" | "hard" | "expert";
+
 interface NewGame {
   size: Size;
   seed: string | undefined;
+  options: BoardOptions;
+  puzzle?: string;
+  difficulty?: Difficulty;
 }
 
 interface Token {
@@ -252,37 +519,93 @@ class ServerRequest {
 
 interface NetWalk {
   root: Pos;
+  roots: Pos[];
   size: Size;
   seed: string;
+  puzzle: string;
   tiles: number[];
+  wrap: boolean;
+  grid: Grid;
+  mask: boolean[] | null;
+  walls: number[];
+  colors: (number | null)[];
+  bridges: Pos[];
+  locked: Pos[];
+  player_locked: Pos[];
+  servers: (number | null)[][];
+  time: number;
+  difficulty: number | null;
+  rule: WinRule;
+  undos: number;
+  par: number;
+  rotations: number;
 }
 
 class Board {
   canvas: HTMLCanvasElement;
   ctx: CanvasRenderingContext2D;
-  root: Pos;
+  roots: Pos[];
   tiles: Tile[];
   width: number;
   height: number;
+  wrap: boolean;
+  grid: Grid;
+  mask: boolean[] | null;
+  walls: number[];
   selected_tile: Pos | null;
   last_selected_tile: Pos | null;
+  hint: Pos | null;
   scale: number;
   xo: number;
   yo: number;
-  on_rotate_tile: (pos: Pos) => void;
+  on_rotate_tile: (pos: Pos, rotation: Rotation) => void;
+  on_lock_tile: (pos: Pos, locked: boolean) => void;
 
-  constructor(width: number, height: number, tiles: number[], root: Pos) {
+  constructor(
+    width: number,
+    height: number,
+    tiles: number[],
+    roots: Pos[],
+    wrap: boolean,
+    grid: Grid,
+    mask: boolean[] | null,
+    walls: number[],
+    colors: (number | null)[],
+    bridges: Pos[],
+    locked: Pos[],
+    player_locked: Pos[],
+    servers: (number | null)[][]
+  ) {
     this.canvas = $("canvas") as HTMLCanvasElement;
     this.ctx = this.canvas.getContext("2d")!;
     this.selected_tile = null;
     this.last_selected_tile = null;
+    this.hint = null;
     this.width = width;
     this.height = height;
-    this.tiles = Board.process_tile_flags(tiles);
-    this.root = root;
+    this.wrap = wrap;
+    this.grid = grid;
+    this.mask = mask;
+    this.walls = walls;
+    let is_bridge = tiles.map(() => false);
+    for (const pos of bridges) {
+      is_bridge[pos.x + pos.y * width] = true;
+    }
+    this.tiles = Board.process_tile_flags(tiles, colors, is_bridge);
+    for (const pos of locked) {
+      this.get_tile(pos).is_locked = true;
+    }
+    for (const pos of player_locked) {
+      this.get_tile(pos).is_player_locked = true;
+    }
+    this.roots = roots;
     this.on_rotate_tile = () => {};
+    this.on_lock_tile = () => {};
 
-    this.update_connectivity();
+    for (let i = 0; i < servers.length; i++) {
+      this.tiles[i].servers = servers[i];
+    }
+    this.update_power();
 
     this.canvas.height = 600;
     this.canvas.width = 600;
@@ -294,37 +617,112 @@ class Board {
       this.select_tile(tile_pos);
     };
 
-    this.canvas.onmousedown = () => {
-      this.rotate_selected_tile();
+    this.canvas.onmousedown = event => {
+      if (event.button == 2) {
+        this.rotate_selected_tile(RotationDirection.Left);
+      } else if (event.button == 1) {
+        event.preventDefault();
+        this.rotate_selected_tile("Half");
+      } else if (event.shiftKey) {
+        this.lock_selected_tile();
+      } else {
+        this.rotate_selected_tile(RotationDirection.Right);
+      }
+    };
+
+    this.canvas.oncontextmenu = event => {
+      event.preventDefault();
     };
 
     // TODO: math floor this if selected tile precision is bad
+    const span = this.get_span();
     this.scale = Math.min(
-      ((this.canvas.width - 10) * 1.0) / this.width,
-      ((this.canvas.height - 10) * 1.0) / this.height
+      ((this.canvas.width - 10) * 1.0) / span.width,
+      ((this.canvas.height - 10) * 1.0) / span.height
     );
     this.xo =
-      Math.floor((this.canvas.width - this.width * this.scale) / 2) + 0.5;
+      Math.floor((this.canvas.width - span.width * this.scale) / 2) + 0.5;
     this.yo =
-      Math.floor((this.canvas.height - this.height * this.scale) / 2) + 0.5;
+      Math.floor((this.canvas.height - span.height * this.scale) / 2) + 0.5;
+  }
+
+  // size of the board in tile widths
+  get_span(): Size {
+    if (this.grid == Grid.Hex) {
+      return {
+        width: this.width + 0.5,
+        height: (this.height - 1) * HEX_ROW + 2 * HEX_RADIUS
+      };
+    }
+
+    return { width: this.width, height: this.height };
+  }
+
+  directions() {
+    if (this.grid == Grid.Hex) {
+      return HEX_DIRECTIONS;
+    }
+
+    return DIRECTIONS;
+  }
+
+  get_tile_center(pos: Pos): Pos {
+    if (this.grid == Grid.Hex) {
+      return {
+        x: this.xo + (pos.x + 0.5 + (pos.y & 1) * 0.5) * this.scale,
+        y: this.yo + (pos.y * HEX_ROW + HEX_RADIUS) * this.scale
+      };
+    }
+
+    return {
+      x: this.xo + (pos.x + 0.5) * this.scale,
+      y: this.yo + (pos.y + 0.5) * this.scale
+    };
   }
 
   set_tile_flag(pos: Pos, n: number) {
     this.get_tile(pos).flag = n;
-    this.update_connectivity();
+    if (this.hint && this.hint.x == pos.x && this.hint.y == pos.y) {
+      this.hint = null;
+    }
     this.draw();
   }
 
-  static process_tile_flags(data: number[]) {
+  // the server keeps track of which network each tile belongs to and only
+  // sends the channels a move changed
+  set_servers(changes: [number, number | null][]) {
+    for (const [node, server] of changes) {
+      this.tiles[node >> 1].servers[node & 1] = server;
+    }
+    this.update_power();
+    this.draw();
+  }
+
+  static process_tile_flags(
+    data: number[],
+    colors: (number | null)[],
+    bridges: boolean[]
+  ) {
     let tiles: Tile[] = [];
     for (let i = 0; i < data.length; i++) {
       const n = data[i];
 
       let tile_kind = TileKind.CONNECTOR;
-      if (flag_count(n) == 1) {
+      if (bridges[i]) {
+        tile_kind = TileKind.BRIDGE;
+      } else if (flag_count(n) == 1) {
         tile_kind = TileKind.TERMINAL;
       }
-      tiles.push({ kind: tile_kind, flag: n, is_powered: false });
+      tiles.push({
+        kind: tile_kind,
+        flag: n,
+        is_powered: false,
+        is_cross_powered: false,
+        servers: [null, null],
+        color: colors[i],
+        is_locked: false,
+        is_player_locked: false
+      });
     }
 
     return tiles;
@@ -332,49 +730,86 @@ class Board {
 
   in_bounds(pos: Pos) {
     return (
-      pos.x >= 0 && pos.y >= 0 && pos.x < this.width && pos.y < this.height
+      pos.x >= 0 &&
+      pos.y >= 0 &&
+      pos.x < this.width &&
+      pos.y < this.height &&
+      (!this.mask || this.mask[pos.x + pos.y * this.width])
     );
   }
 
-  rotate_selected_tile() {
-    if (this.selected_tile && this.in_bounds(this.selected_tile)) {
-      this.on_rotate_tile(this.selected_tile);
+  get_neighbour(pos: Pos, dir: Direction) {
+    if (this.walls[pos.x + pos.y * this.width] & dir.flag) {
+      return null;
     }
-  }
 
-  solve_walk(pos: Pos) {
-    var tile = this.get_tile(pos);
-    tile.is_powered = true;
+    const offset = pos.y & 1 ? dir.odd_offset : dir.offset;
+    let neighbour_pos: Pos = {
+      x: pos.x + offset.x,
+      y: pos.y + offset.y
+    };
 
-    for (let d = 0; d < DIRECTIONS.length; d++) {
-      const dir = DIRECTIONS[d];
-      if ((dir.flag & tile.flag) != 0) {
-        var neighbour_pos: Pos = {
-          x: pos.x + dir.offset.x,
-          y: pos.y + dir.offset.y
-        };
+    if (this.wrap) {
+      neighbour_pos.x = (neighbour_pos.x + this.width) % this.width;
+      neighbour_pos.y = (neighbour_pos.y + this.height) % this.height;
+    }
 
-        if (this.in_bounds(neighbour_pos)) {
-          let neighbour_tile = this.get_tile(neighbour_pos);
-          if (
-            (dir.opposite & neighbour_tile.flag) != 0 &&
-            !neighbour_tile.is_powered
-          ) {
-            this.solve_walk(neighbour_pos);
-          }
-        }
-      }
+    if (this.in_bounds(neighbour_pos)) {
+      return neighbour_pos;
     }
+
+    return null;
+  }
+
+  rotate_selected_tile(rotation: Rotation) {
+    if (
+      this.selected_tile &&
+      this.in_bounds(this.selected_tile) &&
+      !this.get_tile(this.selected_tile).is_locked &&
+      !this.get_tile(this.selected_tile).is_player_locked
+    ) {
+      this.on_rotate_tile(this.selected_tile, rotation);
+    }
+  }
+
+  lock_selected_tile() {
+    if (
+      this.selected_tile &&
+      this.in_bounds(this.selected_tile) &&
+      !this.get_tile(this.selected_tile).is_locked
+    ) {
+      const tile = this.get_tile(this.selected_tile);
+      this.on_lock_tile(this.selected_tile, !tile.is_player_locked);
+    }
+  }
+
+  show_hint(pos: Pos) {
+    this.hint = pos;
+    this.draw();
   }
 
-  update_connectivity() {
-    for (let i = 0; i < this.width; i++) {
-      for (let j = 0; j < this.height; j++) {
-        this.get_tile({ x: i, y: j }).is_powered = false;
+  set_tile_lock(pos: Pos, locked: boolean) {
+    this.get_tile(pos).is_player_locked = locked;
+    this.draw();
+  }
+
+  update_power() {
+    for (const tile of this.tiles) {
+      tile.is_powered = tile.servers[0] !== null;
+      tile.is_cross_powered = tile.servers[1] !== null;
+
+      // terminals only take power from a server of their own colour
+      const server = tile.servers[0];
+      if (tile.kind == TileKind.TERMINAL && server !== null) {
+        if (this.get_tile(this.roots[server]).color !== tile.color) {
+          tile.is_powered = false;
+        }
       }
     }
+  }
 
-    this.solve_walk(this.root);
+  is_colored() {
+    return this.roots.some(root => this.get_tile(root).color !== 0);
   }
 
   clear() {
@@ -419,14 +854,58 @@ class Board {
     );
   }
 
+  draw_locked() {
+    for (var i = 0; i < this.width; ++i) {
+      for (var j = 0; j < this.height; ++j) {
+        const tile = this.get_tile({ x: i, y: j });
+        if (tile.is_locked) {
+          this.ctx.fillStyle = "#e0e0e0";
+        } else if (tile.is_player_locked) {
+          this.ctx.fillStyle = "#f3e3b5";
+        } else {
+          continue;
+        }
+
+        const center = this.get_tile_center({ x: i, y: j });
+        this.ctx.beginPath();
+        this.ctx.arc(center.x, center.y, this.scale / 2.5, 0, 2 * Math.PI);
+        this.ctx.fill();
+      }
+    }
+  }
+
+  draw_hint() {
+    if (!this.hint) {
+      return;
+    }
+
+    const center = this.get_tile_center(this.hint);
+    this.ctx.strokeStyle = "#f00";
+    this.ctx.lineWidth = 2;
+    this.ctx.beginPath();
+    this.ctx.arc(center.x, center.y, this.scale / 2.2, 0, 2 * Math.PI);
+    this.ctx.stroke();
+  }
+
   draw_end_nodes() {
     for (var i = 0; i < this.width; ++i) {
       for (var j = 0; j < this.height; ++j) {
-        const x = this.xo + i * this.scale;
-        const y = this.yo + j * this.scale;
+        const center = this.get_tile_center({ x: i, y: j });
+        const x = center.x - this.scale / 2;
+        const y = center.y - this.scale / 2;
         const tile = this.get_tile({ x: i, y: j });
 
         if (tile.kind == TileKind.TERMINAL) {
+          if (this.is_colored() && tile.color !== null) {
+            this.ctx.fillStyle = COLORS[tile.color % COLORS.length];
+            this.ctx.fillRect(
+              x + this.scale / 5,
+              y + this.scale / 5,
+              (this.scale * 3) / 5,
+              (this.scale * 3) / 5
+            );
+          }
+
           if (tile.is_powered) {
             this.ctx.fillStyle = "#03fc8c";
           } else {
@@ -444,20 +923,38 @@ class Board {
     }
   }
 
-  draw_root() {
-    this.ctx.fillStyle = "#7d32a8";
-    let x = this.xo + this.root.x * this.scale;
-    let y = this.yo + this.root.y * this.scale;
-    this.ctx.fillRect(
-      x + this.scale / 4,
-      y + this.scale / 4,
-      this.scale / 2,
-      this.scale / 2
-    );
+  draw_roots() {
+    for (const root of this.roots) {
+      const color = this.get_tile(root).color || 0;
+      this.ctx.fillStyle = COLORS[color % COLORS.length];
+      const center = this.get_tile_center(root);
+      let x = center.x - this.scale / 2;
+      let y = center.y - this.scale / 2;
+      this.ctx.fillRect(
+        x + this.scale / 4,
+        y + this.scale / 4,
+        this.scale / 2,
+        this.scale / 2
+      );
+    }
   }
 
   draw_lines() {
     this.ctx.strokeStyle = "#aaa";
+    if (this.wrap) {
+      this.ctx.setLineDash([4, 4]);
+    }
+
+    if (this.grid == Grid.Hex) {
+      this.draw_hex_lines();
+      return;
+    }
+
+    if (this.mask) {
+      this.draw_masked_lines();
+      return;
+    }
+
     for (var i = 0; i <= this.width; ++i) {
       this.ctx.moveTo(this.xo + i * this.scale, this.yo);
       this.ctx.lineTo(
@@ -476,7 +973,124 @@ class Board {
     }
   }
 
+  draw_masked_lines() {
+    for (var i = 0; i < this.width; ++i) {
+      for (var j = 0; j < this.height; ++j) {
+        if (this.in_bounds({ x: i, y: j })) {
+          this.ctx.strokeRect(
+            this.xo + i * this.scale,
+            this.yo + j * this.scale,
+            this.scale,
+            this.scale
+          );
+        }
+      }
+    }
+  }
+
+  draw_hex_lines() {
+    const r = HEX_RADIUS * this.scale;
+    for (var i = 0; i < this.width; ++i) {
+      for (var j = 0; j < this.height; ++j) {
+        if (!this.in_bounds({ x: i, y: j })) {
+          continue;
+        }
+
+        const center = this.get_tile_center({ x: i, y: j });
+        this.ctx.beginPath();
+        for (var k = 0; k <= 6; ++k) {
+          const a = ((k * 60 - 90) * Math.PI) / 180;
+          this.ctx.lineTo(
+            center.x + r * Math.cos(a),
+            center.y + r * Math.sin(a)
+          );
+        }
+        this.ctx.stroke();
+      }
+    }
+  }
+
+  draw_hex_tiles() {
+    this.ctx.lineWidth = this.scale / 3;
+    for (var i = 0; i < this.width; ++i) {
+      for (var j = 0; j < this.height; ++j) {
+        const center = this.get_tile_center({ x: i, y: j });
+        const tile = this.get_tile({ x: i, y: j });
+        if (tile.is_powered) {
+          this.ctx.strokeStyle = "#32a852";
+        } else {
+          this.ctx.strokeStyle = "#3b3b3b";
+        }
+
+        for (var d = 0; d < HEX_DIRECTIONS.length; ++d) {
+          if (HEX_DIRECTIONS[d].flag & tile.flag) {
+            const a = (HEX_ANGLES[d] * Math.PI) / 180;
+            this.ctx.beginPath();
+            this.ctx.moveTo(center.x, center.y);
+            this.ctx.lineTo(
+              center.x + (this.scale / 2) * Math.cos(a),
+              center.y + (this.scale / 2) * Math.sin(a)
+            );
+            this.ctx.stroke();
+          }
+        }
+      }
+    }
+    this.ctx.lineWidth = 1;
+  }
+
+  draw_walls() {
+    const directions = this.directions();
+    this.ctx.strokeStyle = "#000";
+    this.ctx.lineWidth = 4;
+    this.ctx.setLineDash([]);
+    for (var i = 0; i < this.width; ++i) {
+      for (var j = 0; j < this.height; ++j) {
+        const walls = this.walls[i + j * this.width];
+        const center = this.get_tile_center({ x: i, y: j });
+        for (var d = 0; d < directions.length; ++d) {
+          if (!(walls & directions[d].flag)) {
+            continue;
+          }
+
+          // the wall is the side of the tile facing the direction
+          var a = 0;
+          var r = 0;
+          var spread = 0;
+          if (this.grid == Grid.Hex) {
+            a = HEX_ANGLES[d];
+            r = HEX_RADIUS * this.scale;
+            spread = 30;
+          } else {
+            a = d * 90 - 90;
+            r = (Math.SQRT2 * this.scale) / 2;
+            spread = 45;
+          }
+
+          const a1 = ((a - spread) * Math.PI) / 180;
+          const a2 = ((a + spread) * Math.PI) / 180;
+          this.ctx.beginPath();
+          this.ctx.moveTo(
+            center.x + r * Math.cos(a1),
+            center.y + r * Math.sin(a1)
+          );
+          this.ctx.lineTo(
+            center.x + r * Math.cos(a2),
+            center.y + r * Math.sin(a2)
+          );
+          this.ctx.stroke();
+        }
+      }
+    }
+    this.ctx.lineWidth = 1;
+  }
+
   draw_tiles() {
+    if (this.grid == Grid.Hex) {
+      this.draw_hex_tiles();
+      return;
+    }
+
     for (var i = 0; i < this.width; ++i) {
       for (var j = 0; j < this.height; ++j) {
         const x = this.xo + i * this.scale;
@@ -485,7 +1099,11 @@ class Board {
           const dir = DIRECTIONS[d];
           const tile = this.get_tile({ x: i, y: j });
           if (dir.flag & tile.flag) {
-            if (tile.is_powered) {
+            const powered =
+              tile_channel(tile, dir.flag) == 1
+                ? tile.is_cross_powered
+                : tile.is_powered;
+            if (powered) {
               this.ctx.fillStyle = "#32a852";
             } else {
               this.ctx.fillStyle = "#3b3b3b";
@@ -507,9 +1125,12 @@ class Board {
     this.clear();
 
     this.draw_lines();
+    this.draw_locked();
+    this.draw_hint();
+    this.draw_walls();
     this.draw_tiles();
     this.draw_end_nodes();
-    this.draw_root();
+    this.draw_roots();
   }
 
   get_tile(pos: Pos) {
@@ -523,6 +1144,10 @@ class Board {
       y: mouse_pos.y - rect.top
     };
 
+    if (this.grid == Grid.Hex) {
+      return this.get_nearest_tile_pos(m_pos);
+    }
+
     var pos = {
       x: Math.floor(m_pos.x / this.scale),
       y: Math.floor(m_pos.y / this.scale)
@@ -530,6 +1155,23 @@ class Board {
 
     return pos;
   }
+
+  get_nearest_tile_pos(p: Pos) {
+    var pos = { x: 0, y: 0 };
+    var best = Infinity;
+    for (var i = 0; i < this.width; ++i) {
+      for (var j = 0; j < this.height; ++j) {
+        const center = this.get_tile_center({ x: i, y: j });
+        const d = Math.pow(center.x - p.x, 2) + Math.pow(center.y - p.y, 2);
+        if (d < best) {
+          best = d;
+          pos = { x: i, y: j };
+        }
+      }
+    }
+
+    return pos;
+  }
 }
 
 class Game {
@@ -541,13 +1183,27 @@ class Game {
   finish_time: number;
   constructor(nw: NetWalk) {
     this.seed = nw.seed;
-    this.board = new Board(nw.size.height, nw.size.width, nw.tiles, nw.root);
+    this.board = new Board(
+      nw.size.height,
+      nw.size.width,
+      nw.tiles,
+      nw.roots,
+      nw.wrap,
+      nw.grid,
+      nw.mask,
+      nw.walls,
+      nw.colors,
+      nw.bridges,
+      nw.locked,
+      nw.player_locked,
+      nw.servers
+    );
     this.is_finished = false;
     this.finish_time = 0;
     this.current_time = 0;
     this.draw();
 
-    this.start_time = Date.now();
+    this.start_time = Date.now() - nw.time;
   }
 
   draw() {
@@ -565,7 +1221,13 @@ class App {
   game: Game | null;
   time_element: HTMLSpanElement;
   time_dif_element: HTMLSpanElement;
+  moves_element: HTMLSpanElement;
+  par: number;
+  mask_pbm: string | null;
   seed_element: HTMLParagraphElement;
+  hint_element: HTMLParagraphElement;
+  rule_element: HTMLParagraphElement;
+  rule: WinRule;
 
   constructor() {
     this.game = null;
@@ -573,7 +1235,13 @@ class App {
     this.token = null;
     this.time_element = $("time")! as HTMLSpanElement;
     this.time_dif_element = $("time-dif")! as HTMLSpanElement;
+    this.moves_element = $("moves")! as HTMLSpanElement;
+    this.par = 0;
+    this.mask_pbm = null;
     this.seed_element = $("seed")! as HTMLParagraphElement;
+    this.hint_element = $("hint-text")! as HTMLParagraphElement;
+    this.rule_element = $("rule-text")! as HTMLParagraphElement;
+    this.rule = WinRule.Terminals;
 
     this.init_listeners();
     this.init_socket();
@@ -602,55 +1270,147 @@ class App {
       case ClientMethodKind.SetGame:
         let nw: NetWalk = JSON.parse(req.data);
         this.game = new Game(nw);
-        this.game.board.on_rotate_tile = pos => {
+        this.game.board.on_rotate_tile = (pos, rotation) => {
           let req: RotateTile = {
             pos: pos,
-            direction: RotationDirection.Right
+            rotation: rotation
           };
           this.send(ServerMethodKind.RotateTile, req);
         };
+        this.game.board.on_lock_tile = (pos, locked) => {
+          let req: TileLock = { pos: pos };
+          this.send(
+            locked ? ServerMethodKind.LockTile : ServerMethodKind.UnlockTile,
+            req
+          );
+        };
         this.seed_element.hidden = false;
-        this.seed_element.textContent = "seed: " + nw.seed;
+        this.seed_element.textContent = "puzzle: " + nw.puzzle;
+        if (nw.difficulty !== null) {
+          this.seed_element.textContent += " (difficulty " + Math.round(nw.difficulty) + ")";
+        }
         this.time_dif_element.hidden = true;
+        this.par = nw.par;
+        this.show_moves(nw.rotations);
+        this.hint_element.hidden = true;
+        this.rule_element.hidden = true;
+        this.rule = nw.rule;
+        break;
+
+      case ClientMethodKind.ShowHint:
+        let hint: ShowHint = JSON.parse(req.data);
+        if (this.game && hint) {
+          this.game.board.show_hint(hint.hint.pos);
+          this.game.start_time = Date.now() - hint.time;
+          this.hint_element.textContent =
+            hint.explanation.length > 0
+              ? hint.explanation.join(". ") + "."
+              : "only one way to turn this tile works";
+          this.hint_element.hidden = false;
+        }
+        break;
+
+      case ClientMethodKind.SetReplay:
+        let replay: Replay = JSON.parse(req.data);
+        if (replay) {
+          let a = document.createElement("a");
+          a.href = URL.createObjectURL(
+            new Blob([req.data], { type: "application/json" })
+          );
+          a.download = replay.puzzle + ".replay.json";
+          a.click();
+          URL.revokeObjectURL(a.href);
+        }
+        break;
+
+      case ClientMethodKind.UpdateTileLock:
+        let lock: UpdateTileLock = JSON.parse(req.data);
+        if (this.game && lock) {
+          this.game.board.set_tile_lock(lock.pos, lock.locked);
+        }
         break;
 
       case ClientMethodKind.UpdateGameState:
         let data: UpdateGameState = JSON.parse(req.data);
         if (this.game && data) {
-          this.game.board.set_tile_flag(data.pos, data.flag);
+          if (!data.no_op) {
+            this.game.board.set_tile_flag(data.pos, data.flag);
+            this.game.board.set_servers(data.servers);
+          }
+          this.show_moves(data.rotations);
+
+          // only the classic rule asks for more than the lights being on
+          if (this.rule == WinRule.Classic && data.violation) {
+            this.rule_element.textContent = describe_violation(data.violation);
+            this.rule_element.hidden = false;
+          } else {
+            this.rule_element.hidden = true;
+          }
 
           if (data.is_solved && !this.game.is_finished) {
             this.game.is_finished = true;
             this.game.finish_time = data.time;
 
-            let sr = save(
-              { width: this.game.board.width, height: this.game.board.height },
-              this.game.seed,
-              data.time
-            );
-            if (sr.last_high_score) {
-              const dif = Math.abs(data.time - sr.last_high_score);
-
-              this.time_dif_element.textContent = "";
-              if (data.time < sr.last_high_score) {
-                this.time_dif_element.style.color = "#0f0";
-                this.time_dif_element.textContent += "-";
-              } else {
-                this.time_dif_element.style.color = "#f00";
-                this.time_dif_element.textContent += "+";
-              }
-              this.time_dif_element.textContent += format_time(dif);
-              this.time_dif_element.hidden = false;
+            this.send(ServerMethodKind.SubmitResult, {});
+          }
+        }
+        break;
+
+      case ClientMethodKind.AcceptResult:
+        let result: AcceptedResult = JSON.parse(req.data);
+        if (this.game && result) {
+          this.game.finish_time = result.time;
+          this.show_moves(result.rotations);
+          this.moves_element.textContent +=
+            " " + "★".repeat(result.stars) + "☆".repeat(3 - result.stars);
+
+          let sr = save(
+            { width: this.game.board.width, height: this.game.board.height },
+            this.game.seed,
+            result.time,
+            result.undos,
+            result.id
+          );
+          if (sr.last_high_score) {
+            const dif = Math.abs(result.time - sr.last_high_score);
+
+            this.time_dif_element.textContent = "";
+            if (result.time < sr.last_high_score) {
+              this.time_dif_element.style.color = "#0f0";
+              this.time_dif_element.textContent += "-";
+            } else {
+              this.time_dif_element.style.color = "#f00";
+              this.time_dif_element.textContent += "+";
             }
+            this.time_dif_element.textContent += format_time(dif);
+            this.time_dif_element.hidden = false;
           }
         }
         break;
 
+      case ClientMethodKind.RejectResult:
+        let rejection: Rejection = JSON.parse(req.data);
+        this.rule_element.textContent = "result not accepted: " + rejection;
+        this.rule_element.hidden = false;
+        break;
+
       default:
         break;
     }
   }
 
+  show_moves(rotations: number) {
+    this.moves_element.textContent =
+      "moves: " + rotations + " (par " + this.par + ")";
+    this.moves_element.hidden = false;
+  }
+
+  undo(redo: boolean) {
+    if (this.game && !this.game.is_finished) {
+      this.send(redo ? ServerMethodKind.Redo : ServerMethodKind.Undo, {});
+    }
+  }
+
   send(method: ServerMethodKind, data: any | null) {
     let req = new ServerRequest(method, this.token, data);
 
@@ -658,6 +1418,57 @@ class App {
   }
 
   init_listeners() {
+    // the tab may have missed updates while it was hidden
+    document.onvisibilitychange = () => {
+      if (!document.hidden && this.game) {
+        this.send(ServerMethodKind.Resync, {});
+      }
+    };
+
+    $("hint")!.onclick = () => {
+      if (this.game && !this.game.is_finished) {
+        this.send(ServerMethodKind.Hint, {});
+      }
+    };
+
+    // a chosen file replaces the named shapes until it's cleared again
+    $("mask-file")!.onchange = event => {
+      const input = event.target as HTMLInputElement;
+      this.mask_pbm = null;
+      if (input.files && input.files.length > 0) {
+        let reader = new FileReader();
+        reader.onload = () => {
+          const bytes = new Uint8Array(reader.result as ArrayBuffer);
+          this.mask_pbm = Array.from(bytes)
+            .map(b => ("0" + b.toString(16)).slice(-2))
+            .join("");
+        };
+        reader.readAsArrayBuffer(input.files[0]);
+      }
+    };
+
+    $("undo")!.onclick = () => this.undo(false);
+    $("redo")!.onclick = () => this.undo(true);
+    $("replay")!.onclick = () => {
+      if (this.game) {
+        this.send(ServerMethodKind.GetReplay, {});
+      }
+    };
+
+    document.onkeydown = e => {
+      if (!(e.ctrlKey || e.metaKey)) {
+        return;
+      }
+
+      if (e.key == "z" || e.key == "Z") {
+        this.undo(e.shiftKey);
+        e.preventDefault();
+      } else if (e.key == "y") {
+        this.undo(true);
+        e.preventDefault();
+      }
+    };
+
     $("5x5")!.onclick = () => {
       this.new_game({ height: 5, width: 5 });
     };
@@ -678,6 +1489,19 @@ class App {
       this.new_game({ height: 20, width: 20 });
     };
 
+    $("load")!.onclick = () => {
+      const puzzle = ($("puzzle") as HTMLInputElement).value.trim();
+      if (puzzle) {
+        let ng: NewGame = {
+          size: { width: 0, height: 0 },
+          seed: undefined,
+          options: this.board_options(),
+          puzzle: puzzle
+        };
+        this.send(ServerMethodKind.NewGame, ng);
+      }
+    };
+
     setInterval(() => {
       if (this.game) {
         var t = 0;
@@ -693,8 +1517,45 @@ class App {
     }, 20);
   }
 
+  board_options() {
+    let options: BoardOptions = {
+      unique: ($("unique") as HTMLInputElement).checked,
+      wrap: ($("wrap") as HTMLInputElement).checked,
+      grid: ($("hex") as HTMLInputElement).checked ? Grid.Hex : Grid.Square,
+      mask: null,
+      walls: parseInt(($("walls") as HTMLInputElement).value) / 100,
+      servers: parseInt(($("servers") as HTMLInputElement).value),
+      colors: parseInt(($("colors") as HTMLInputElement).value),
+      bridges: parseInt(($("bridges") as HTMLInputElement).value) / 100,
+      hints: null,
+      generator: {
+        algorithm: ($("algorithm") as HTMLSelectElement).value as Algorithm,
+        root: ($("root") as HTMLSelectElement).value as RootPlacement
+      },
+      rule: ($("rule") as HTMLSelectElement).value as WinRule
+    };
+
+    const hints = parseInt(($("hints") as HTMLInputElement).value);
+    if (hints > 0) {
+      options.hints = { Percent: hints };
+    }
+
+    const mask = ($("mask") as HTMLSelectElement).value;
+    if (this.mask_pbm) {
+      options.mask = { Pbm: this.mask_pbm };
+    } else if (mask) {
+      options.mask = { Named: mask };
+    }
+
+    return options;
+  }
+
   new_game(size: Size, seed?: string) {
-    let ng: NewGame = { size: size, seed: seed };
+    let ng: NewGame = { size: size, seed: seed, options: this.board_options() };
+    const difficulty = ($("difficulty") as HTMLSelectElement).value;
+    if (difficulty) {
+      ng.difficulty = difficulty as Difficulty;
+    }
     this.send(ServerMethodKind.NewGame, ng);
   }
 }